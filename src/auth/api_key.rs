@@ -0,0 +1,324 @@
+use crate::http::authenticator::ApiKeyVerifier;
+use crate::http::user_token::TokenUser;
+use crate::utils::{hash::signing_none_secret, random::next_random_alphanumeric};
+use anyhow::anyhow;
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tracing::{debug, info};
+
+pub const DEFAULT_API_KEY_PREFIX: &str = "rsk";
+const API_KEY_SEPARATOR: char = '_';
+const API_KEY_ID_LEN: usize = 12;
+const API_KEY_SECRET_LEN: usize = 32;
+const API_KEY_CHECKSUM_LEN: usize = 6;
+
+/// the stored form of an api key. the raw key is only returned once on issuance.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: String,
+    /// sha256 hex of the raw key
+    pub key_hash: String,
+    pub user: TokenUser,
+    pub scopes: Vec<String>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub last_used_at: Option<i64>,
+    /// id of the key replacing this one
+    pub rotated_to: Option<String>,
+    pub revoked: bool,
+}
+
+impl ApiKey {
+    pub fn is_active(&self, now: i64) -> bool {
+        !self.revoked && self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+}
+
+pub trait ApiKeyStore: Send + Sync {
+    fn save<'a>(&'a self, key: &'a ApiKey) -> BoxFuture<'a, Result<(), anyhow::Error>>;
+
+    fn find<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Option<ApiKey>, anyhow::Error>>;
+
+    fn find_by_user(&self, user_id: i64) -> BoxFuture<'_, Result<Vec<ApiKey>, anyhow::Error>>;
+
+    fn touch<'a>(&'a self, id: &'a str, used_at: i64) -> BoxFuture<'a, Result<(), anyhow::Error>>;
+}
+
+#[derive(Default)]
+pub struct InMemoryApiKeyStore {
+    keys: RwLock<HashMap<String, ApiKey>>,
+}
+
+impl InMemoryApiKeyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ApiKeyStore for InMemoryApiKeyStore {
+    fn save<'a>(&'a self, key: &'a ApiKey) -> BoxFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            self.keys
+                .write()
+                .map_err(|_| anyhow!("api key store poisoned"))?
+                .insert(key.id.clone(), key.clone());
+            Ok(())
+        })
+    }
+
+    fn find<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Option<ApiKey>, anyhow::Error>> {
+        Box::pin(async move {
+            Ok(self
+                .keys
+                .read()
+                .map_err(|_| anyhow!("api key store poisoned"))?
+                .get(id)
+                .cloned())
+        })
+    }
+
+    fn find_by_user(&self, user_id: i64) -> BoxFuture<'_, Result<Vec<ApiKey>, anyhow::Error>> {
+        Box::pin(async move {
+            Ok(self
+                .keys
+                .read()
+                .map_err(|_| anyhow!("api key store poisoned"))?
+                .values()
+                .filter(|key| key.user.user_id == user_id)
+                .cloned()
+                .collect())
+        })
+    }
+
+    fn touch<'a>(&'a self, id: &'a str, used_at: i64) -> BoxFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            if let Some(key) = self
+                .keys
+                .write()
+                .map_err(|_| anyhow!("api key store poisoned"))?
+                .get_mut(id)
+            {
+                key.last_used_at = Some(used_at);
+            }
+            Ok(())
+        })
+    }
+}
+
+fn checksum(body: &str) -> String {
+    signing_none_secret(body)[..API_KEY_CHECKSUM_LEN].to_owned()
+}
+
+/// `<prefix>_<id>_<secret><checksum>`
+/// the checksum let typos and garbage be rejected without touching the store.
+fn split_key<'a>(prefix: &str, raw: &'a str) -> Option<&'a str> {
+    if raw.len() <= API_KEY_CHECKSUM_LEN {
+        return None;
+    }
+    let (body, sum) = raw.split_at(raw.len() - API_KEY_CHECKSUM_LEN);
+    if checksum(body) != sum {
+        return None;
+    }
+    let mut parts = body.splitn(3, API_KEY_SEPARATOR);
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(p), Some(id), Some(_)) if p == prefix && id.len() == API_KEY_ID_LEN => Some(id),
+        _ => None,
+    }
+}
+
+pub struct ApiKeyManager {
+    store: Arc<dyn ApiKeyStore>,
+    prefix: String,
+}
+
+impl ApiKeyManager {
+    pub fn new(store: Arc<dyn ApiKeyStore>) -> Self {
+        Self::new_with_prefix(store, DEFAULT_API_KEY_PREFIX)
+    }
+
+    pub fn new_with_prefix(store: Arc<dyn ApiKeyStore>, prefix: &str) -> Self {
+        Self {
+            store,
+            prefix: prefix.to_owned(),
+        }
+    }
+
+    fn generate(&self) -> (String, String) {
+        let id = next_random_alphanumeric(API_KEY_ID_LEN);
+        let body = format!(
+            "{}{}{}{}{}",
+            self.prefix,
+            API_KEY_SEPARATOR,
+            id,
+            API_KEY_SEPARATOR,
+            next_random_alphanumeric(API_KEY_SECRET_LEN)
+        );
+        let raw = format!("{}{}", body, checksum(&body));
+        (id, raw)
+    }
+
+    /// returns the raw key and its stored form. the raw key can not be recovered later.
+    pub async fn issue(
+        &self,
+        user: TokenUser,
+        scopes: Vec<String>,
+        ttl_sec: Option<i64>,
+    ) -> Result<(String, ApiKey), anyhow::Error> {
+        let now = chrono::Utc::now().timestamp();
+        let (id, raw) = self.generate();
+        let key = ApiKey {
+            id,
+            key_hash: signing_none_secret(&raw),
+            user,
+            scopes,
+            created_at: now,
+            expires_at: ttl_sec.map(|ttl| now + ttl),
+            last_used_at: None,
+            rotated_to: None,
+            revoked: false,
+        };
+        self.store.save(&key).await?;
+        info!(id = key.id, user_id = key.user.user_id, "api key issued");
+        Ok((raw, key))
+    }
+
+    /// `Ok(None)` if the key is unknown, expired or revoked
+    pub async fn verify(&self, raw: &str) -> Result<Option<ApiKey>, anyhow::Error> {
+        let Some(id) = split_key(&self.prefix, raw) else {
+            debug!("malformed api key");
+            return Ok(None);
+        };
+        let Some(key) = self.store.find(id).await? else {
+            return Ok(None);
+        };
+        let now = chrono::Utc::now().timestamp();
+        if key.key_hash != signing_none_secret(raw) || !key.is_active(now) {
+            return Ok(None);
+        }
+        self.store.touch(&key.id, now).await?;
+        Ok(Some(key))
+    }
+
+    /// issues a replacement key with the same owner and scopes.
+    /// the old key keeps working for `grace_sec` seconds.
+    pub async fn rotate(
+        &self,
+        id: &str,
+        grace_sec: i64,
+    ) -> Result<(String, ApiKey), anyhow::Error> {
+        let mut old = self
+            .store
+            .find(id)
+            .await?
+            .ok_or(anyhow!("api key {} not found", id))?;
+        let now = chrono::Utc::now().timestamp();
+        if !old.is_active(now) {
+            return Err(anyhow!("api key {} is not active", id));
+        }
+        // its grace period would be extended otherwise
+        if let Some(rotated_to) = &old.rotated_to {
+            return Err(anyhow!(
+                "api key {} is already rotated to {}",
+                id,
+                rotated_to
+            ));
+        }
+        let ttl_sec = old.expires_at.map(|expires_at| expires_at - now);
+        let (raw, new) = self
+            .issue(old.user.clone(), old.scopes.clone(), ttl_sec)
+            .await?;
+        let grace_until = now + grace_sec;
+        old.expires_at = Some(old.expires_at.map_or(grace_until, |e| e.min(grace_until)));
+        old.rotated_to = Some(new.id.clone());
+        self.store.save(&old).await?;
+        info!(id = id, rotated_to = new.id, "api key rotated");
+        Ok((raw, new))
+    }
+
+    pub async fn revoke(&self, id: &str) -> Result<(), anyhow::Error> {
+        let mut key = self
+            .store
+            .find(id)
+            .await?
+            .ok_or(anyhow!("api key {} not found", id))?;
+        key.revoked = true;
+        self.store.save(&key).await?;
+        info!(id = id, "api key revoked");
+        Ok(())
+    }
+
+    pub async fn list(&self, user_id: i64) -> Result<Vec<ApiKey>, anyhow::Error> {
+        self.store.find_by_user(user_id).await
+    }
+}
+
+impl ApiKeyVerifier for ApiKeyManager {
+    fn verify<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> BoxFuture<'a, Result<Option<TokenUser>, anyhow::Error>> {
+        Box::pin(async move {
+            Ok(ApiKeyManager::verify(self, key)
+                .await?
+                .map(|key| TokenUser {
+                    scopes: key.scopes,
+                    ..key.user
+                }))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_api_key_lifecycle() {
+        let manager = ApiKeyManager::new(Arc::new(InMemoryApiKeyStore::new()));
+        let user = TokenUser {
+            user_id: 7,
+            ..Default::default()
+        };
+        let (raw, key) = manager
+            .issue(user, vec!["orders:read".to_owned()], None)
+            .await
+            .unwrap();
+        assert!(raw.starts_with("rsk_"));
+        assert_ne!(key.key_hash, raw);
+
+        let verified = manager.verify(&raw).await.unwrap().unwrap();
+        assert_eq!(verified.scopes, vec!["orders:read".to_owned()]);
+        assert!(verified.last_used_at.is_none());
+        assert!(manager.list(7).await.unwrap()[0].last_used_at.is_some());
+
+        // broken checksum
+        let mut tampered = raw.clone();
+        tampered.insert(raw.len() - API_KEY_CHECKSUM_LEN, 'x');
+        assert!(manager.verify(&tampered).await.unwrap().is_none());
+
+        // old key is still valid within grace period
+        let (rotated, _) = manager.rotate(&key.id, 60).await.unwrap();
+        assert!(manager.verify(&raw).await.unwrap().is_some());
+        assert!(manager.verify(&rotated).await.unwrap().is_some());
+        assert!(manager.rotate(&key.id, 60).await.is_err());
+        manager.revoke(&key.id).await.unwrap();
+        assert!(manager.verify(&raw).await.unwrap().is_none());
+        assert!(manager.verify(&rotated).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_api_key_grace_period() {
+        let manager = ApiKeyManager::new(Arc::new(InMemoryApiKeyStore::new()));
+        let (raw, key) = manager
+            .issue(TokenUser::default(), vec![], None)
+            .await
+            .unwrap();
+        let (rotated, _) = manager.rotate(&key.id, 1).await.unwrap();
+        assert!(manager.verify(&raw).await.unwrap().is_some());
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        assert!(manager.verify(&raw).await.unwrap().is_none());
+        assert!(manager.verify(&rotated).await.unwrap().is_some());
+    }
+}
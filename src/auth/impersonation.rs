@@ -0,0 +1,155 @@
+use crate::http::middlewares::jwt_authentication::{JwtAuthConfig, JwtClaimsBuilder};
+use crate::http::user_token::{Actor, TokenUser};
+use anyhow::anyhow;
+use axum::http::{Method, StatusCode};
+use derive_builder::Builder;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+/// tracing target of impersonation records, route it to the audit log
+pub const AUDIT_TARGET: &str = "audit::impersonation";
+const DEFAULT_REQUIRED_SCOPE: &str = "admin:impersonate";
+
+#[derive(Clone, Deserialize, Builder)]
+pub struct ImpersonationConfig {
+    /// tokens are signed by it, so that `jwt_authentication` accepts them
+    pub jwt: JwtAuthConfig,
+    /// actors must have it
+    #[builder(default = "DEFAULT_REQUIRED_SCOPE.to_owned()")]
+    #[serde(default = "default_required_scope")]
+    pub required_scope: String,
+    /// impersonation tokens carry at most these scopes of the impersonated user
+    #[builder(default)]
+    #[serde(default)]
+    pub granted_scopes: Vec<String>,
+    #[builder(default = "900")]
+    #[serde(default = "default_ttl_sec")]
+    pub ttl_sec: u64,
+}
+
+fn default_required_scope() -> String {
+    DEFAULT_REQUIRED_SCOPE.to_owned()
+}
+
+fn default_ttl_sec() -> u64 {
+    900
+}
+
+pub struct Impersonation {
+    config: ImpersonationConfig,
+}
+
+impl Impersonation {
+    pub fn new(config: ImpersonationConfig) -> Self {
+        Self { config }
+    }
+
+    /// token of `subject` carrying `actor` in its `act` claim.
+    /// impersonation tokens can not be used to impersonate again.
+    pub fn impersonate(
+        &self,
+        actor: &TokenUser,
+        subject: &TokenUser,
+    ) -> Result<String, anyhow::Error> {
+        if !actor.has_scope(&self.config.required_scope) {
+            warn!(
+                target: AUDIT_TARGET,
+                actor = actor.user_id,
+                user_id = subject.user_id,
+                "impersonation denied"
+            );
+            return Err(anyhow!("insufficient scope to impersonate"));
+        }
+        if actor.is_impersonated() || subject.is_impersonated() {
+            return Err(anyhow!("chained impersonation"));
+        }
+        if actor.user_id == subject.user_id {
+            return Err(anyhow!("impersonating oneself"));
+        }
+        let user = TokenUser {
+            scopes: subject
+                .scopes
+                .iter()
+                .filter(|s| self.config.granted_scopes.contains(s))
+                .cloned()
+                .collect(),
+            act: Some(Actor {
+                sub: actor.user_id.to_string(),
+            }),
+            ..subject.clone()
+        };
+        info!(
+            target: AUDIT_TARGET,
+            actor = actor.user_id,
+            user_id = user.user_id,
+            tenant_id = user.tenant_id,
+            scopes = ?user.scopes,
+            ttl_sec = self.config.ttl_sec,
+            "impersonation started"
+        );
+        self.config
+            .jwt
+            .issue_token(JwtClaimsBuilder::default().cla(user), self.config.ttl_sec)
+    }
+}
+
+/// records requests made with impersonation tokens, no-op for others.
+/// called by every middleware accepting impersonated users: `authentication`
+/// (session and cookie included), `jwt_authentication` and `token_user_trust`
+pub(crate) fn audit(user: &TokenUser, ip: &str, method: &Method, path: &str, status: StatusCode) {
+    if let Some(act) = &user.act {
+        info!(
+            target: AUDIT_TARGET,
+            actor = act.sub,
+            user_id = user.user_id,
+            tenant_id = user.tenant_id,
+            ip = ip,
+            method = %method,
+            path = path,
+            status = status.as_u16(),
+            "impersonated request"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::middlewares::jwt_authentication::JwtAuthConfigBuilder;
+
+    #[test]
+    fn test_impersonate() {
+        let jwt = JwtAuthConfigBuilder::default()
+            .issuer("issuer".to_owned())
+            .secret("secret".to_owned())
+            .build()
+            .unwrap();
+        let impersonation = Impersonation::new(
+            ImpersonationConfigBuilder::default()
+                .jwt(jwt.clone())
+                .granted_scopes(vec!["orders:read".to_owned()])
+                .build()
+                .unwrap(),
+        );
+        let support = TokenUser {
+            user_id: 1,
+            scopes: vec![DEFAULT_REQUIRED_SCOPE.to_owned()],
+            ..Default::default()
+        };
+        let customer = TokenUser {
+            user_id: 2,
+            scopes: vec!["orders:read".to_owned(), "orders:write".to_owned()],
+            tenant_id: Some("acme".to_owned()),
+            ..Default::default()
+        };
+        let token = impersonation.impersonate(&support, &customer).unwrap();
+        let user = jwt.parse_token_user(&token).unwrap();
+        assert_eq!(user.user_id, 2);
+        assert_eq!(user.tenant_id.as_deref(), Some("acme"));
+        assert_eq!(user.scopes, vec!["orders:read".to_owned()]);
+        assert_eq!(user.act.as_ref().unwrap().sub, "1");
+
+        assert!(impersonation.impersonate(&user, &support).is_err());
+        assert!(impersonation.impersonate(&customer, &support).is_err());
+    }
+}
@@ -0,0 +1,6 @@
+pub mod api_key;
+pub mod impersonation;
+pub mod open_api;
+pub mod session;
+pub mod oidc;
+pub mod oauth;
@@ -0,0 +1,400 @@
+use crate::http::authenticator::{decode_basic, Challenge, AUTH_METHOD_KEY_BASIC, DEFAULT_REALM};
+use crate::http::middlewares::jwt_authentication::{JwtAuthConfig, JwtClaimsBuilder};
+use crate::http::user_token::TokenUser;
+use crate::utils::http_error_handler::{ErrorResponse, Result};
+use anyhow::anyhow;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Form, Json, Router};
+use derive_builder::Builder;
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+const GRANT_TYPE_CLIENT_CREDENTIALS: &str = "client_credentials";
+const TOKEN_TYPE_BEARER: &str = "Bearer";
+
+/// error codes of RFC 6749 section 5.2
+const ERROR_INVALID_REQUEST: &str = "invalid_request";
+const ERROR_INVALID_CLIENT: &str = "invalid_client";
+const ERROR_INVALID_SCOPE: &str = "invalid_scope";
+const ERROR_UNSUPPORTED_GRANT_TYPE: &str = "unsupported_grant_type";
+
+/// a registered machine-to-machine caller
+#[derive(Debug, Clone, Deserialize)]
+pub struct OAuthClient {
+    pub client_id: String,
+    /// bcrypt hash
+    pub secret_hash: String,
+    /// scopes the client may be granted
+    pub scopes: Vec<String>,
+    /// the identity tokens of the client act as
+    pub user: TokenUser,
+}
+
+pub trait OAuthClientStore: Send + Sync {
+    fn find<'a>(
+        &'a self,
+        client_id: &'a str,
+    ) -> BoxFuture<'a, Result<Option<OAuthClient>, anyhow::Error>>;
+}
+
+impl OAuthClientStore for HashMap<String, OAuthClient> {
+    fn find<'a>(
+        &'a self,
+        client_id: &'a str,
+    ) -> BoxFuture<'a, Result<Option<OAuthClient>, anyhow::Error>> {
+        Box::pin(async move { Ok(self.get(client_id).cloned()) })
+    }
+}
+
+#[derive(Clone, Deserialize, Builder)]
+pub struct OAuthServerConfig {
+    /// tokens are signed by it, so that `jwt_authentication` accepts them
+    pub jwt: JwtAuthConfig,
+    #[builder(default = "3600")]
+    #[serde(default = "default_token_ttl_sec")]
+    pub token_ttl_sec: u64,
+}
+
+fn default_token_ttl_sec() -> u64 {
+    3600
+}
+
+pub struct OAuthServer {
+    config: OAuthServerConfig,
+    clients: Arc<dyn OAuthClientStore>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenRequest {
+    grant_type: Option<String>,
+    scope: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: u64,
+    pub scope: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IntrospectionRequest {
+    token: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+
+/// RFC 7662. only `active` is given for inactive tokens.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+}
+
+fn oauth_error(status_code: StatusCode, error: &str, message: &str) -> ErrorResponse {
+    ErrorResponse::new_with_error(status_code, error, Some(message.to_owned()))
+}
+
+/// `invalid_client` is answered with a basic challenge
+fn invalid_client() -> Response {
+    let mut response = oauth_error(
+        StatusCode::UNAUTHORIZED,
+        ERROR_INVALID_CLIENT,
+        "client authentication failed",
+    )
+    .into_response();
+    if let Ok(challenge) = Challenge::basic(DEFAULT_REALM).to_header_value().parse() {
+        response
+            .headers_mut()
+            .insert(header::WWW_AUTHENTICATE, challenge);
+    }
+    response
+}
+
+impl OAuthServer {
+    pub fn new(config: OAuthServerConfig, clients: Arc<dyn OAuthClientStore>) -> Self {
+        Self { config, clients }
+    }
+
+    /// `client_secret_basic` or `client_secret_post`
+    async fn authenticate_client(
+        &self,
+        headers: &HeaderMap,
+        client_id: Option<String>,
+        client_secret: Option<String>,
+    ) -> Result<OAuthClient, anyhow::Error> {
+        let basic = match crate::http::authenticator::extract_authorization(headers) {
+            Some((AUTH_METHOD_KEY_BASIC, encoded)) => Some(decode_basic(encoded)?),
+            _ => None,
+        };
+        let (client_id, client_secret) = basic
+            .or(client_id.zip(client_secret))
+            .ok_or(anyhow!("missing client credentials"))?;
+        let client = self
+            .clients
+            .find(&client_id)
+            .await?
+            .ok_or(anyhow!("unknown client {}", client_id))?;
+        let secret_hash = client.secret_hash.clone();
+        // bcrypt is cpu bound
+        if !tokio::task::spawn_blocking(move || bcrypt::verify(client_secret, &secret_hash))
+            .await??
+        {
+            return Err(anyhow!("secret mismatch for client {}", client_id));
+        }
+        Ok(client)
+    }
+
+    /// requested scopes must all be allowed, all allowed ones are granted if none requested
+    fn grant_scopes(client: &OAuthClient, requested: Option<&str>) -> Option<Vec<String>> {
+        match requested.map(str::split_whitespace) {
+            None => Some(client.scopes.clone()),
+            Some(requested) => requested
+                .map(|scope| client.scopes.iter().find(|s| *s == scope).cloned())
+                .collect(),
+        }
+    }
+
+    pub fn issue_token(
+        &self,
+        client: &OAuthClient,
+        scopes: Vec<String>,
+    ) -> Result<TokenResponse, anyhow::Error> {
+        let scope = scopes.join(" ");
+        let access_token = self.config.jwt.issue_token(
+            JwtClaimsBuilder::default()
+                .client_id(Some(client.client_id.clone()))
+                .cla(TokenUser {
+                    scopes,
+                    ..client.user.clone()
+                }),
+            self.config.token_ttl_sec,
+        )?;
+        Ok(TokenResponse {
+            access_token,
+            token_type: TOKEN_TYPE_BEARER.to_owned(),
+            expires_in: self.config.token_ttl_sec,
+            scope,
+        })
+    }
+
+    pub fn introspect(&self, token: &str) -> IntrospectionResponse {
+        match self.config.jwt.validate(token) {
+            Ok(claims) => IntrospectionResponse {
+                active: true,
+                scope: Some(claims.cla.scopes.join(" ")),
+                client_id: claims.client_id,
+                sub: Some(claims.cla.user_id.to_string()),
+                token_type: Some(TOKEN_TYPE_BEARER.to_owned()),
+                exp: Some(claims.exp),
+                iat: Some(claims.iat),
+                iss: Some(claims.iss),
+            },
+            Err(_) => IntrospectionResponse::default(),
+        }
+    }
+}
+
+async fn token(
+    State(server): State<Arc<OAuthServer>>,
+    headers: HeaderMap,
+    Form(request): Form<TokenRequest>,
+) -> Result<Response> {
+    match request.grant_type.as_deref() {
+        Some(GRANT_TYPE_CLIENT_CREDENTIALS) => {}
+        Some(_) => {
+            return Err(oauth_error(
+                StatusCode::BAD_REQUEST,
+                ERROR_UNSUPPORTED_GRANT_TYPE,
+                "only client_credentials is supported",
+            ))
+        }
+        None => {
+            return Err(oauth_error(
+                StatusCode::BAD_REQUEST,
+                ERROR_INVALID_REQUEST,
+                "missing grant_type",
+            ))
+        }
+    }
+    let client = match server
+        .authenticate_client(&headers, request.client_id, request.client_secret)
+        .await
+    {
+        Ok(client) => client,
+        Err(e) => {
+            warn!(err = e.to_string(), "oauth client authentication failed");
+            return Ok(invalid_client());
+        }
+    };
+    let Some(scopes) = OAuthServer::grant_scopes(&client, request.scope.as_deref()) else {
+        return Err(oauth_error(
+            StatusCode::BAD_REQUEST,
+            ERROR_INVALID_SCOPE,
+            "scope not allowed",
+        ));
+    };
+    let response = server.issue_token(&client, scopes)?;
+    info!(
+        client_id = client.client_id,
+        scope = response.scope,
+        "oauth token issued"
+    );
+    // RFC 6749 section 5.1
+    Ok((
+        [
+            (header::CACHE_CONTROL, "no-store"),
+            (header::PRAGMA, "no-cache"),
+        ],
+        Json(response),
+    )
+        .into_response())
+}
+
+async fn introspect(
+    State(server): State<Arc<OAuthServer>>,
+    headers: HeaderMap,
+    Form(request): Form<IntrospectionRequest>,
+) -> Result<Response> {
+    if let Err(e) = server
+        .authenticate_client(&headers, request.client_id, request.client_secret)
+        .await
+    {
+        warn!(
+            err = e.to_string(),
+            "oauth introspection client authentication failed"
+        );
+        return Ok(invalid_client());
+    }
+    let Some(token) = request.token else {
+        return Err(oauth_error(
+            StatusCode::BAD_REQUEST,
+            ERROR_INVALID_REQUEST,
+            "missing token",
+        ));
+    };
+    Ok(Json(server.introspect(&token)).into_response())
+}
+
+/// `POST /oauth/token` and `POST /oauth/introspect`
+pub fn router(server: Arc<OAuthServer>) -> Router {
+    Router::new()
+        .route("/oauth/token", post(token))
+        .route("/oauth/introspect", post(introspect))
+        .with_state(server)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::middlewares::jwt_authentication::JwtAuthConfigBuilder;
+    use axum::body::Body;
+    use axum::extract::Request;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    async fn post_form(app: &Router, path: &str, form: &str) -> (StatusCode, serde_json::Value) {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::post(path)
+                    .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                    .body(Body::from(form.to_owned()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_client_credentials() {
+        let jwt = JwtAuthConfigBuilder::default()
+            .issuer("issuer".to_owned())
+            .secret("secret".to_owned())
+            .build()
+            .unwrap();
+        let clients = HashMap::from([(
+            "svc".to_owned(),
+            OAuthClient {
+                client_id: "svc".to_owned(),
+                secret_hash: bcrypt::hash("s3cret", 4).unwrap(),
+                scopes: vec!["orders:read".to_owned(), "orders:write".to_owned()],
+                user: TokenUser {
+                    user_id: 100,
+                    ..Default::default()
+                },
+            },
+        )]);
+        let config = OAuthServerConfigBuilder::default()
+            .jwt(jwt.clone())
+            .build()
+            .unwrap();
+        let app = router(Arc::new(OAuthServer::new(config, Arc::new(clients))));
+
+        let (status, body) = post_form(
+            &app,
+            "/oauth/token",
+            "grant_type=client_credentials&scope=orders:read&client_id=svc&client_secret=s3cret",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let access_token = body["access_token"].as_str().unwrap().to_owned();
+        let token_user = jwt.parse_token_user(&access_token).unwrap();
+        assert_eq!(token_user.user_id, 100);
+        assert_eq!(token_user.scopes, vec!["orders:read".to_owned()]);
+
+        let (status, body) = post_form(
+            &app,
+            "/oauth/introspect",
+            &format!("token={}&client_id=svc&client_secret=s3cret", access_token),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["active"], true);
+        assert_eq!(body["client_id"], "svc");
+        assert_eq!(body["scope"], "orders:read");
+
+        let (status, body) = post_form(
+            &app,
+            "/oauth/token",
+            "grant_type=client_credentials&client_id=svc&client_secret=wrong",
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"], ERROR_INVALID_CLIENT);
+
+        let (status, body) = post_form(
+            &app,
+            "/oauth/token",
+            "grant_type=client_credentials&scope=admin&client_id=svc&client_secret=s3cret",
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], ERROR_INVALID_SCOPE);
+    }
+}
@@ -0,0 +1,718 @@
+use crate::auth::session::SessionStore;
+use crate::http::cookie::{self, CookieConfig, SameSite};
+use crate::http::middlewares::jwt_authentication::Audience;
+use crate::http::user_token::TokenUser;
+use crate::utils::hash::SignedContent;
+use crate::utils::http_error_handler::{ErrorResponse, Result};
+use crate::utils::{base64, random::next_random_alphanumeric};
+use ::base64::{engine::general_purpose::STANDARD, Engine};
+use anyhow::anyhow;
+use axum::body::Bytes;
+use axum::extract::{Query, State};
+use axum::http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use derive_builder::Builder;
+use futures_util::future::BoxFuture;
+use http_body_util::{BodyExt, Full};
+use hyper_util::client::legacy::connect::{Connect, HttpConnector};
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::sync::{Arc, RwLock};
+use tracing::{error, info, warn};
+
+const DISCOVERY_PATH: &str = "/.well-known/openid-configuration";
+const PKCE_VERIFIER_LEN: usize = 64;
+const STATE_LEN: usize = 32;
+const NONCE_LEN: usize = 32;
+// only asymmetric keys can be published by an idp
+const ID_TOKEN_ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+#[derive(Debug, Clone, Deserialize, Builder)]
+pub struct OidcConfig {
+    /// discovery document is loaded from `<issuer_url>/.well-known/openid-configuration`
+    pub issuer_url: String,
+    pub client_id: String,
+    /// sent by `client_secret_basic`, public clients rely on pkce only
+    #[builder(default)]
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    /// absolute url of the callback handler registered at the idp
+    pub redirect_uri: String,
+    #[builder(
+        default = "vec![\"openid\".to_owned(), \"profile\".to_owned(), \"email\".to_owned()]"
+    )]
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<String>,
+    /// signs the login state kept in a cookie during the redirect
+    pub state_secret: String,
+    #[builder(default = "\"oidc_state\".to_owned()")]
+    #[serde(default = "default_state_cookie_name")]
+    pub state_cookie_name: String,
+    /// how long a login may take at the idp
+    #[builder(default = "300")]
+    #[serde(default = "default_login_expire_sec")]
+    pub login_expire_sec: i64,
+    /// the cookie carrying the session key once logged in
+    pub session_cookie: CookieConfig,
+    /// where to go after login if the login request didn't tell
+    #[builder(default = "\"/\".to_owned()")]
+    #[serde(default = "default_post_login_redirect")]
+    pub post_login_redirect: String,
+}
+
+fn default_scopes() -> Vec<String> {
+    vec![
+        "openid".to_owned(),
+        "profile".to_owned(),
+        "email".to_owned(),
+    ]
+}
+
+fn default_state_cookie_name() -> String {
+    "oidc_state".to_owned()
+}
+
+fn default_login_expire_sec() -> i64 {
+    300
+}
+
+fn default_post_login_redirect() -> String {
+    "/".to_owned()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiscoveryDocument {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+    #[serde(default)]
+    pub userinfo_endpoint: Option<String>,
+    #[serde(default)]
+    pub end_session_endpoint: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: Audience,
+    pub exp: i64,
+    pub iat: i64,
+    #[serde(default)]
+    pub nonce: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub preferred_username: Option<String>,
+    /// any other claim
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// kept in a signed cookie between the redirect to the idp and the callback
+#[derive(Debug, Serialize, Deserialize)]
+struct LoginState {
+    state: String,
+    nonce: String,
+    code_verifier: String,
+    redirect_to: Option<String>,
+}
+
+/// maps the identity asserted by the idp to a local user, e.g. by looking up or provisioning it
+pub trait OidcClaimsMapper: Send + Sync {
+    fn map<'a>(
+        &'a self,
+        claims: &'a IdTokenClaims,
+    ) -> BoxFuture<'a, Result<TokenUser, anyhow::Error>>;
+}
+
+pub trait OidcHttpClient: Send + Sync {
+    fn get<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<Bytes, anyhow::Error>>;
+
+    fn post_form<'a>(
+        &'a self,
+        url: &'a str,
+        form: String,
+        authorization: Option<String>,
+    ) -> BoxFuture<'a, Result<Bytes, anyhow::Error>>;
+}
+
+/// plain http by default, give a tls connector by `new_with_connector` for real idps
+pub struct HyperHttpClient<C> {
+    client: Client<C, Full<Bytes>>,
+}
+
+impl HyperHttpClient<HttpConnector> {
+    pub fn new() -> Self {
+        Self {
+            client: Client::builder(TokioExecutor::new()).build_http(),
+        }
+    }
+}
+
+impl Default for HyperHttpClient<HttpConnector> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C> HyperHttpClient<C>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    pub fn new_with_connector(connector: C) -> Self {
+        Self {
+            client: Client::builder(TokioExecutor::new()).build(connector),
+        }
+    }
+
+    async fn send(&self, request: Request<Full<Bytes>>) -> Result<Bytes, anyhow::Error> {
+        let uri = request.uri().to_string();
+        let response = self.client.request(request).await?;
+        let status = response.status();
+        let body = response.into_body().collect().await?.to_bytes();
+        if !status.is_success() {
+            return Err(anyhow!(
+                "request {} failed with {}: {}",
+                uri,
+                status,
+                String::from_utf8_lossy(&body)
+            ));
+        }
+        Ok(body)
+    }
+}
+
+impl<C> OidcHttpClient for HyperHttpClient<C>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    fn get<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<Bytes, anyhow::Error>> {
+        Box::pin(async move {
+            self.send(
+                Request::get(url)
+                    .header(header::ACCEPT, "application/json")
+                    .body(Full::default())?,
+            )
+            .await
+        })
+    }
+
+    fn post_form<'a>(
+        &'a self,
+        url: &'a str,
+        form: String,
+        authorization: Option<String>,
+    ) -> BoxFuture<'a, Result<Bytes, anyhow::Error>> {
+        Box::pin(async move {
+            let mut request = Request::builder()
+                .method(Method::POST)
+                .uri(url)
+                .header(header::ACCEPT, "application/json")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+            if let Some(authorization) = authorization {
+                request = request.header(header::AUTHORIZATION, authorization);
+            }
+            self.send(request.body(Full::from(form))?).await
+        })
+    }
+}
+
+/// pkce `S256` challenge of a verifier
+fn code_challenge(code_verifier: &str) -> String {
+    base64::encode(digest(&SHA256, code_verifier.as_bytes()))
+}
+
+/// only local paths, or it is an open redirect
+/// browsers drop tabs and newlines, `/\t/evil.com` would become `//evil.com`
+fn is_local_redirect(redirect_to: &str) -> bool {
+    redirect_to.starts_with('/')
+        && !redirect_to.starts_with("//")
+        && !redirect_to
+            .chars()
+            .any(|c| c == '\\' || c.is_ascii_control() || c.is_whitespace())
+}
+
+pub struct OidcClient {
+    config: OidcConfig,
+    discovery: DiscoveryDocument,
+    jwks: RwLock<JwkSet>,
+    http: Arc<dyn OidcHttpClient>,
+    mapper: Arc<dyn OidcClaimsMapper>,
+}
+
+impl OidcClient {
+    /// loads the discovery document and the idp keys
+    pub async fn discover(
+        config: OidcConfig,
+        http: Arc<dyn OidcHttpClient>,
+        mapper: Arc<dyn OidcClaimsMapper>,
+    ) -> Result<Self, anyhow::Error> {
+        let url = format!(
+            "{}{}",
+            config.issuer_url.trim_end_matches('/'),
+            DISCOVERY_PATH
+        );
+        let discovery: DiscoveryDocument = serde_json::from_slice(&http.get(&url).await?)?;
+        if discovery.issuer.trim_end_matches('/') != config.issuer_url.trim_end_matches('/') {
+            return Err(anyhow!(
+                "discovered issuer {} mismatches {}",
+                discovery.issuer,
+                config.issuer_url
+            ));
+        }
+        let jwks: JwkSet = serde_json::from_slice(&http.get(&discovery.jwks_uri).await?)?;
+        info!(
+            issuer = discovery.issuer,
+            keys = jwks.keys.len(),
+            "oidc idp discovered"
+        );
+        Ok(Self {
+            config,
+            discovery,
+            jwks: RwLock::new(jwks),
+            http,
+            mapper,
+        })
+    }
+
+    pub fn discovery(&self) -> &DiscoveryDocument {
+        &self.discovery
+    }
+
+    fn state_cookie(&self) -> CookieConfig {
+        CookieConfig {
+            name: self.config.state_cookie_name.clone(),
+            http_only: true,
+            secure: self.config.session_cookie.secure,
+            // sent along the top-level redirect back from the idp
+            same_site: SameSite::Lax,
+            path: "/".to_owned(),
+            domain: self.config.session_cookie.domain.clone(),
+            max_age_sec: Some(self.config.login_expire_sec),
+        }
+    }
+
+    /// the url to redirect the user agent to, and the signed login state to be kept by it
+    pub fn authorization_request(
+        &self,
+        redirect_to: Option<String>,
+    ) -> Result<(String, String), anyhow::Error> {
+        let login_state = LoginState {
+            state: next_random_alphanumeric(STATE_LEN),
+            nonce: next_random_alphanumeric(NONCE_LEN),
+            code_verifier: next_random_alphanumeric(PKCE_VERIFIER_LEN),
+            redirect_to: redirect_to.filter(|r| is_local_redirect(r)),
+        };
+        let query = serde_urlencoded::to_string([
+            ("response_type", "code"),
+            ("client_id", &self.config.client_id),
+            ("redirect_uri", &self.config.redirect_uri),
+            ("scope", &self.config.scopes.join(" ")),
+            ("state", &login_state.state),
+            ("nonce", &login_state.nonce),
+            (
+                "code_challenge",
+                &code_challenge(&login_state.code_verifier),
+            ),
+            ("code_challenge_method", "S256"),
+        ])?;
+        let separator = if self.discovery.authorization_endpoint.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+        let url = format!(
+            "{}{}{}",
+            self.discovery.authorization_endpoint, separator, query
+        );
+        let signed = SignedContent::new_with_expire(login_state, self.config.login_expire_sec)
+            .to_signed_string(&self.config.state_secret)?;
+        Ok((url, signed))
+    }
+
+    /// verifies the callback against the login state, redeems the code and validates the id token.
+    /// returns the mapped user and where to go next.
+    pub async fn exchange(
+        &self,
+        code: &str,
+        state: &str,
+        signed_login_state: &str,
+    ) -> Result<(TokenUser, IdTokenClaims, Option<String>), anyhow::Error> {
+        let login_state =
+            SignedContent::<LoginState>::parse(signed_login_state, &self.config.state_secret)?
+                .content;
+        if login_state.state != state {
+            return Err(anyhow!("oidc state mismatch"));
+        }
+        let form = serde_urlencoded::to_string([
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.config.redirect_uri),
+            ("client_id", &self.config.client_id),
+            ("code_verifier", &login_state.code_verifier),
+        ])?;
+        let authorization = self.config.client_secret.as_ref().map(|secret| {
+            format!(
+                "Basic {}",
+                STANDARD.encode(format!("{}:{}", self.config.client_id, secret))
+            )
+        });
+        let response: TokenResponse = serde_json::from_slice(
+            &self
+                .http
+                .post_form(&self.discovery.token_endpoint, form, authorization)
+                .await?,
+        )?;
+        let claims = self.validate_id_token(&response.id_token).await?;
+        if claims.nonce.as_deref() != Some(login_state.nonce.as_str()) {
+            return Err(anyhow!("oidc nonce mismatch"));
+        }
+        let token_user = self.mapper.map(&claims).await?;
+        Ok((token_user, claims, login_state.redirect_to))
+    }
+
+    fn decoding_key(&self, kid: &str) -> Result<Option<DecodingKey>, anyhow::Error> {
+        let jwks = self.jwks.read().map_err(|_| anyhow!("jwks poisoned"))?;
+        jwks.find(kid)
+            .map(DecodingKey::from_jwk)
+            .transpose()
+            .map_err(anyhow::Error::from)
+    }
+
+    async fn validate_id_token(&self, id_token: &str) -> Result<IdTokenClaims, anyhow::Error> {
+        let header = decode_header(id_token)?;
+        if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+            return Err(anyhow!("unsupported id token algorithm {:?}", header.alg));
+        }
+        let kid = header.kid.ok_or(anyhow!("id token without kid"))?;
+        let key = match self.decoding_key(&kid)? {
+            Some(key) => key,
+            None => {
+                // the idp may have rotated its keys
+                warn!(kid = kid, "unknown oidc key, reloading jwks");
+                let jwks: JwkSet =
+                    serde_json::from_slice(&self.http.get(&self.discovery.jwks_uri).await?)?;
+                *self.jwks.write().map_err(|_| anyhow!("jwks poisoned"))? = jwks;
+                self.decoding_key(&kid)?
+                    .ok_or(anyhow!("unknown oidc key {}", kid))?
+            }
+        };
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.discovery.issuer]);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        Ok(decode::<IdTokenClaims>(id_token, &key, &validation)?.claims)
+    }
+}
+
+#[derive(Clone)]
+struct OidcState {
+    client: Arc<OidcClient>,
+    sessions: Arc<dyn SessionStore>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginQuery {
+    redirect_to: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+fn redirect(location: &str, headers: HeaderMap) -> Result<Response> {
+    let mut response = (StatusCode::FOUND, headers).into_response();
+    response
+        .headers_mut()
+        .insert(header::LOCATION, HeaderValue::from_str(location)?);
+    Ok(response)
+}
+
+async fn login(
+    State(state): State<OidcState>,
+    Query(query): Query<LoginQuery>,
+) -> Result<Response> {
+    let (url, login_state) = state.client.authorization_request(query.redirect_to)?;
+    let mut headers = HeaderMap::new();
+    cookie::set_cookie(&mut headers, &state.client.state_cookie(), &login_state)?;
+    redirect(&url, headers)
+}
+
+async fn callback(
+    State(state): State<OidcState>,
+    Query(query): Query<CallbackQuery>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let client = &state.client;
+    if let Some(error) = query.error {
+        warn!(
+            error = error,
+            description = query.error_description,
+            "oidc login refused by idp"
+        );
+        return Err(ErrorResponse::new_no_auth());
+    }
+    let (Some(code), Some(callback_state)) = (query.code, query.state) else {
+        return Err(ErrorResponse::new_with_message("missing code or state"));
+    };
+    let Some(login_state) = cookie::get_cookie(&headers, &client.config.state_cookie_name) else {
+        return Err(ErrorResponse::new_no_auth());
+    };
+    let (token_user, claims, redirect_to) = client
+        .exchange(&code, &callback_state, login_state)
+        .await
+        .map_err(|e| {
+            error!(err = e.to_string(), "oidc callback error");
+            ErrorResponse::new_no_auth()
+        })?;
+    let (session_key, _) = state.sessions.create(token_user).await?;
+    info!(sub = claims.sub, "oidc login");
+    let mut headers = HeaderMap::new();
+    cookie::clear_cookie(&mut headers, &client.state_cookie())?;
+    cookie::set_cookie(&mut headers, &client.config.session_cookie, &session_key)?;
+    redirect(
+        redirect_to
+            .as_deref()
+            .unwrap_or(&client.config.post_login_redirect),
+        headers,
+    )
+}
+
+/// `GET /login?redirect_to=/path` redirects to the idp,
+/// `GET /callback` establishes a session and sets the session cookie.
+/// nest it where `redirect_uri` points to.
+pub fn router(client: Arc<OidcClient>, sessions: Arc<dyn SessionStore>) -> Router {
+    Router::new()
+        .route("/login", get(login))
+        .route("/callback", get(callback))
+        .with_state(OidcState { client, sessions })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::session::{InMemorySessionStore, SessionConfig};
+    use crate::http::cookie::CookieConfigBuilder;
+    use axum::body::Body;
+    use axum::extract::Form;
+    use axum::routing::post;
+    use axum::Json;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use tower::ServiceExt;
+
+    struct SubMapper;
+
+    impl OidcClaimsMapper for SubMapper {
+        fn map<'a>(
+            &'a self,
+            claims: &'a IdTokenClaims,
+        ) -> BoxFuture<'a, Result<TokenUser, anyhow::Error>> {
+            Box::pin(async move {
+                Ok(TokenUser {
+                    user_id: claims.sub.parse()?,
+                    ..Default::default()
+                })
+            })
+        }
+    }
+
+    #[derive(Clone)]
+    struct MockIdp {
+        issuer: String,
+        pkcs8: Arc<Vec<u8>>,
+        // code -> (nonce, code challenge)
+        codes: Arc<Mutex<HashMap<String, (String, String)>>>,
+    }
+
+    async fn token(
+        State(idp): State<MockIdp>,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> Response {
+        let Some((nonce, challenge)) = idp.codes.lock().unwrap().remove(&form["code"]) else {
+            return StatusCode::BAD_REQUEST.into_response();
+        };
+        if code_challenge(&form["code_verifier"]) != challenge {
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+        let now = chrono::Utc::now().timestamp();
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some("k1".to_owned());
+        let id_token = encode(
+            &header,
+            &json!({
+                "iss": idp.issuer, "sub": "42", "aud": "client", "exp": now + 60,
+                "iat": now, "nonce": nonce, "email": "u@example.com"
+            }),
+            &EncodingKey::from_ed_der(&idp.pkcs8),
+        )
+        .unwrap();
+        Json(json!({"id_token": id_token, "access_token": "at", "token_type": "Bearer"}))
+            .into_response()
+    }
+
+    async fn start_mock_idp() -> MockIdp {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let jwks = json!({"keys": [{
+            "kty": "OKP", "crv": "Ed25519", "kid": "k1", "alg": "EdDSA", "use": "sig",
+            "x": base64::encode(key_pair.public_key().as_ref())
+        }]});
+        let discovery = json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{}/authorize", issuer),
+            "token_endpoint": format!("{}/token", issuer),
+            "jwks_uri": format!("{}/jwks", issuer),
+        });
+        let idp = MockIdp {
+            issuer,
+            pkcs8: Arc::new(pkcs8.as_ref().to_vec()),
+            codes: Default::default(),
+        };
+        let app = Router::new()
+            .route(DISCOVERY_PATH, get(|| async move { Json(discovery) }))
+            .route("/jwks", get(|| async move { Json(jwks) }))
+            .route("/token", post(token))
+            .with_state(idp.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        idp
+    }
+
+    fn set_cookies(response: &Response) -> Vec<String> {
+        response
+            .headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .map(|v| v.to_str().unwrap().split(';').next().unwrap().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_is_local_redirect() {
+        assert!(is_local_redirect("/orders?id=1"));
+        for redirect_to in [
+            "https://evil.com",
+            "//evil.com",
+            "/\\evil.com",
+            "/\t/evil.com",
+            "/\n/evil.com",
+            "/\r/evil.com",
+            "/ /evil.com",
+            "orders",
+        ] {
+            assert!(!is_local_redirect(redirect_to), "{:?}", redirect_to);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oidc_login() {
+        let idp = start_mock_idp().await;
+        let config = OidcConfigBuilder::default()
+            .issuer_url(idp.issuer.clone())
+            .client_id("client".to_owned())
+            .redirect_uri("http://app/oidc/callback".to_owned())
+            .state_secret("secret".to_owned())
+            .session_cookie(
+                CookieConfigBuilder::default()
+                    .name("sid".to_owned())
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        let client = OidcClient::discover(
+            config,
+            Arc::new(HyperHttpClient::new()),
+            Arc::new(SubMapper),
+        )
+        .await
+        .unwrap();
+        let sessions = Arc::new(InMemorySessionStore::new(SessionConfig::default()));
+        let app = router(Arc::new(client), sessions.clone());
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/login?redirect_to=/orders")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        let params: HashMap<String, String> =
+            serde_urlencoded::from_str(location.split_once('?').unwrap().1).unwrap();
+        assert_eq!(params["code_challenge_method"], "S256");
+        idp.codes.lock().unwrap().insert(
+            "code".to_owned(),
+            (params["nonce"].clone(), params["code_challenge"].clone()),
+        );
+        let state_cookie = set_cookies(&response).remove(0);
+
+        // state mismatch
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/callback?code=code&state=other")
+                    .header(header::COOKIE, &state_cookie)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app
+            .oneshot(
+                Request::get(format!("/callback?code=code&state={}", params["state"]))
+                    .header(header::COOKIE, &state_cookie)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(response.headers()[header::LOCATION], "/orders");
+        let session_cookie = set_cookies(&response).pop().unwrap();
+        let session_key = session_cookie.strip_prefix("sid=").unwrap();
+        let session = sessions.resolve(session_key).await.unwrap().unwrap();
+        assert_eq!(session.user.user_id, 42);
+    }
+}
@@ -0,0 +1,144 @@
+use crate::http::user_token::TokenUser;
+use crate::utils::hash::{signing, signing_none_secret, verify_signing};
+use anyhow::anyhow;
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+const OPEN_TOKEN_FIELD_APP_ID: &str = "app_id";
+const OPEN_TOKEN_FIELD_TIMESTAMP: &str = "timestamp";
+const OPEN_TOKEN_FIELD_NONCE: &str = "nonce";
+const OPEN_TOKEN_FIELD_SIGNATURE: &str = "signature";
+
+/// a third-party caller of the open platform
+#[derive(Debug, Clone, Deserialize)]
+pub struct OpenPartner {
+    pub app_id: String,
+    /// hmac secret shared with the partner
+    pub secret: String,
+    /// the identity requests of the partner act as
+    pub user: TokenUser,
+}
+
+pub trait OpenPartnerStore: Send + Sync {
+    fn find<'a>(
+        &'a self,
+        app_id: &'a str,
+    ) -> BoxFuture<'a, Result<Option<OpenPartner>, anyhow::Error>>;
+}
+
+impl OpenPartnerStore for HashMap<String, OpenPartner> {
+    fn find<'a>(
+        &'a self,
+        app_id: &'a str,
+    ) -> BoxFuture<'a, Result<Option<OpenPartner>, anyhow::Error>> {
+        Box::pin(async move { Ok(self.get(app_id).cloned()) })
+    }
+}
+
+/// `X-Open-Token: app_id=<app id>,timestamp=<unix seconds>,nonce=<nonce>,signature=<hex>`
+#[derive(Debug, Clone)]
+pub struct OpenToken {
+    pub app_id: String,
+    pub timestamp: i64,
+    pub nonce: String,
+    pub signature: String,
+}
+
+impl OpenToken {
+    pub fn parse(raw: &str) -> Result<Self, anyhow::Error> {
+        let fields: HashMap<&str, &str> = raw
+            .split(',')
+            .filter_map(|field| field.trim().split_once('='))
+            .collect();
+        let field = |name: &str| {
+            fields
+                .get(name)
+                .map(|v| v.to_string())
+                .ok_or(anyhow!("missing open token field {}", name))
+        };
+        Ok(Self {
+            app_id: field(OPEN_TOKEN_FIELD_APP_ID)?,
+            timestamp: field(OPEN_TOKEN_FIELD_TIMESTAMP)?.parse()?,
+            nonce: field(OPEN_TOKEN_FIELD_NONCE)?,
+            signature: field(OPEN_TOKEN_FIELD_SIGNATURE)?,
+        })
+    }
+
+    pub fn to_header_value(&self) -> String {
+        format!(
+            "{}={},{}={},{}={},{}={}",
+            OPEN_TOKEN_FIELD_APP_ID,
+            self.app_id,
+            OPEN_TOKEN_FIELD_TIMESTAMP,
+            self.timestamp,
+            OPEN_TOKEN_FIELD_NONCE,
+            self.nonce,
+            OPEN_TOKEN_FIELD_SIGNATURE,
+            self.signature
+        )
+    }
+}
+
+fn canonical_query(query: Option<&str>) -> String {
+    let mut pairs: Vec<&str> = query
+        .unwrap_or("")
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .collect();
+    pairs.sort_unstable();
+    pairs.join("&")
+}
+
+/// the string both sides sign:
+/// method, path, sorted query, sha256 hex of body, timestamp and nonce joined by `\n`
+pub fn canonical_request(
+    method: &str,
+    path: &str,
+    query: Option<&str>,
+    body: &[u8],
+    timestamp: i64,
+    nonce: &str,
+) -> String {
+    format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method.to_uppercase(),
+        path,
+        canonical_query(query),
+        signing_none_secret(body),
+        timestamp,
+        nonce
+    )
+}
+
+/// what a partner does on its side
+pub fn sign_request(
+    partner_app_id: &str,
+    secret: &str,
+    method: &str,
+    path: &str,
+    query: Option<&str>,
+    body: &[u8],
+    nonce: &str,
+) -> OpenToken {
+    let timestamp = chrono::Utc::now().timestamp();
+    let canonical = canonical_request(method, path, query, body, timestamp, nonce);
+    OpenToken {
+        app_id: partner_app_id.to_owned(),
+        timestamp,
+        nonce: nonce.to_owned(),
+        signature: signing(&canonical, secret),
+    }
+}
+
+pub fn verify_request(
+    token: &OpenToken,
+    secret: &str,
+    method: &str,
+    path: &str,
+    query: Option<&str>,
+    body: &[u8],
+) -> bool {
+    let canonical = canonical_request(method, path, query, body, token.timestamp, &token.nonce);
+    verify_signing(&canonical, secret, &token.signature)
+}
@@ -0,0 +1,425 @@
+use crate::http::user_token::TokenUser;
+use crate::utils::{hash::signing_none_secret, random::next_random_alphanumeric};
+use anyhow::anyhow;
+use derive_builder::Builder;
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
+use tracing::debug;
+
+const SESSION_KEY_LEN: usize = 48;
+
+#[derive(Debug, Clone, Deserialize, Builder)]
+#[serde(default)]
+pub struct SessionConfig {
+    /// idle timeout, every access slides the expiration
+    #[builder(default = "1800")]
+    pub ttl_sec: i64,
+    /// maximum sessions kept by the in-memory store, least recently used ones are evicted
+    #[builder(default = "10000")]
+    pub capacity: usize,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            ttl_sec: 1800,
+            capacity: 10000,
+        }
+    }
+}
+
+/// the opaque session key is never stored, sessions are identified by its sha256
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub user: TokenUser,
+    pub created_at: i64,
+    pub last_accessed_at: i64,
+    pub expires_at: i64,
+}
+
+pub fn new_session_key() -> String {
+    next_random_alphanumeric(SESSION_KEY_LEN)
+}
+
+pub fn session_id(key: &str) -> String {
+    signing_none_secret(key)
+}
+
+pub trait SessionStore: Send + Sync {
+    /// returns the session key given to the client
+    fn create(&self, user: TokenUser) -> BoxFuture<'_, Result<(String, Session), anyhow::Error>>;
+
+    /// `Ok(None)` if unknown or expired. slides the expiration.
+    fn resolve<'a>(&'a self, key: &'a str)
+        -> BoxFuture<'a, Result<Option<Session>, anyhow::Error>>;
+
+    /// active sessions of a user
+    fn list(&self, user_id: i64) -> BoxFuture<'_, Result<Vec<Session>, anyhow::Error>>;
+
+    fn terminate<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), anyhow::Error>>;
+
+    fn terminate_all(&self, user_id: i64) -> BoxFuture<'_, Result<(), anyhow::Error>>;
+}
+
+#[derive(Default)]
+struct LruSessions {
+    // id -> (session, access tick)
+    sessions: HashMap<String, (Session, u64)>,
+    // access tick -> id, the first is the least recently used
+    accesses: BTreeMap<u64, String>,
+    users: HashMap<i64, HashSet<String>>,
+    tick: u64,
+}
+
+impl LruSessions {
+    fn insert(&mut self, session: Session) {
+        self.tick += 1;
+        self.accesses.insert(self.tick, session.id.clone());
+        self.users
+            .entry(session.user.user_id)
+            .or_default()
+            .insert(session.id.clone());
+        if let Some((_, tick)) = self
+            .sessions
+            .insert(session.id.clone(), (session, self.tick))
+        {
+            self.accesses.remove(&tick);
+        }
+    }
+
+    fn remove(&mut self, id: &str) -> Option<Session> {
+        let (session, tick) = self.sessions.remove(id)?;
+        self.accesses.remove(&tick);
+        if let Some(ids) = self.users.get_mut(&session.user.user_id) {
+            ids.remove(id);
+            if ids.is_empty() {
+                self.users.remove(&session.user.user_id);
+            }
+        }
+        Some(session)
+    }
+
+    fn evict(&mut self, capacity: usize) {
+        while self.sessions.len() > capacity {
+            let Some((_, id)) = self.accesses.pop_first() else {
+                break;
+            };
+            debug!(id = id, "least recently used session evicted");
+            self.remove(&id);
+        }
+    }
+}
+
+/// single instance only
+pub struct InMemorySessionStore {
+    config: SessionConfig,
+    sessions: Mutex<LruSessions>,
+}
+
+impl InMemorySessionStore {
+    pub fn new(config: SessionConfig) -> Self {
+        Self {
+            config,
+            sessions: Mutex::new(LruSessions::default()),
+        }
+    }
+
+    fn with_sessions<R>(&self, f: impl FnOnce(&mut LruSessions) -> R) -> Result<R, anyhow::Error> {
+        let mut sessions = self
+            .sessions
+            .lock()
+            .map_err(|_| anyhow!("session store poisoned"))?;
+        Ok(f(&mut sessions))
+    }
+}
+
+impl SessionStore for InMemorySessionStore {
+    fn create(&self, user: TokenUser) -> BoxFuture<'_, Result<(String, Session), anyhow::Error>> {
+        Box::pin(async move {
+            let key = new_session_key();
+            let now = chrono::Utc::now().timestamp();
+            let session = Session {
+                id: session_id(&key),
+                user,
+                created_at: now,
+                last_accessed_at: now,
+                expires_at: now + self.config.ttl_sec,
+            };
+            self.with_sessions(|sessions| {
+                sessions.insert(session.clone());
+                sessions.evict(self.config.capacity);
+            })?;
+            Ok((key, session))
+        })
+    }
+
+    fn resolve<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Session>, anyhow::Error>> {
+        Box::pin(async move {
+            let id = session_id(key);
+            let now = chrono::Utc::now().timestamp();
+            self.with_sessions(|sessions| {
+                let mut session = sessions.remove(&id)?;
+                if session.expires_at <= now {
+                    return None;
+                }
+                session.last_accessed_at = now;
+                session.expires_at = now + self.config.ttl_sec;
+                sessions.insert(session.clone());
+                Some(session)
+            })
+        })
+    }
+
+    fn list(&self, user_id: i64) -> BoxFuture<'_, Result<Vec<Session>, anyhow::Error>> {
+        Box::pin(async move {
+            let now = chrono::Utc::now().timestamp();
+            self.with_sessions(|sessions| {
+                sessions
+                    .users
+                    .get(&user_id)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|id| sessions.sessions.get(id))
+                    .map(|(session, _)| session)
+                    .filter(|session| session.expires_at > now)
+                    .cloned()
+                    .collect()
+            })
+        })
+    }
+
+    fn terminate<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            self.with_sessions(|sessions| {
+                sessions.remove(id);
+            })
+        })
+    }
+
+    fn terminate_all(&self, user_id: i64) -> BoxFuture<'_, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            self.with_sessions(|sessions| {
+                for id in sessions.users.remove(&user_id).unwrap_or_default() {
+                    sessions.remove(&id);
+                }
+            })
+        })
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use redis_store::RedisSessionStore;
+
+#[cfg(feature = "redis")]
+mod redis_store {
+    use super::*;
+    use fred::clients::RedisPool;
+    use fred::interfaces::{KeysInterface, SetsInterface};
+    use fred::types::{Expiration, SetOptions};
+
+    const SESSION_KEY_PREFIX: &str = "session:id:";
+    const SESSION_USER_KEY_PREFIX: &str = "session:user:";
+
+    /// shared by instances. `capacity` is not applied, redis eviction policy does it.
+    pub struct RedisSessionStore {
+        config: SessionConfig,
+        redis: RedisPool,
+    }
+
+    impl RedisSessionStore {
+        pub fn new(config: SessionConfig, redis: RedisPool) -> Self {
+            Self { config, redis }
+        }
+
+        async fn save(&self, session: &Session) -> Result<(), anyhow::Error> {
+            let user_key = format!("{}{}", SESSION_USER_KEY_PREFIX, session.user.user_id);
+            self.redis
+                .set::<(), _, _>(
+                    format!("{}{}", SESSION_KEY_PREFIX, session.id),
+                    serde_json::to_string(session)?,
+                    Some(Expiration::EX(self.config.ttl_sec)),
+                    None,
+                    false,
+                )
+                .await?;
+            self.redis
+                .sadd::<(), _, _>(&user_key, session.id.as_str())
+                .await?;
+            // the index lives as long as the latest session
+            self.redis
+                .expire::<(), _>(&user_key, self.config.ttl_sec)
+                .await?;
+            Ok(())
+        }
+    }
+
+    impl SessionStore for RedisSessionStore {
+        fn create(
+            &self,
+            user: TokenUser,
+        ) -> BoxFuture<'_, Result<(String, Session), anyhow::Error>> {
+            Box::pin(async move {
+                let key = new_session_key();
+                let now = chrono::Utc::now().timestamp();
+                let session = Session {
+                    id: session_id(&key),
+                    user,
+                    created_at: now,
+                    last_accessed_at: now,
+                    expires_at: now + self.config.ttl_sec,
+                };
+                self.save(&session).await?;
+                Ok((key, session))
+            })
+        }
+
+        fn resolve<'a>(
+            &'a self,
+            key: &'a str,
+        ) -> BoxFuture<'a, Result<Option<Session>, anyhow::Error>> {
+            Box::pin(async move {
+                let id = session_id(key);
+                let Some(session) = self
+                    .redis
+                    .get::<Option<String>, _>(format!("{}{}", SESSION_KEY_PREFIX, id))
+                    .await?
+                else {
+                    return Ok(None);
+                };
+                let mut session: Session = serde_json::from_str(&session)?;
+                let now = chrono::Utc::now().timestamp();
+                session.last_accessed_at = now;
+                session.expires_at = now + self.config.ttl_sec;
+                // `SET XX` never brings back a session terminated since the `GET`
+                let refreshed: Option<String> = self
+                    .redis
+                    .set(
+                        format!("{}{}", SESSION_KEY_PREFIX, id),
+                        serde_json::to_string(&session)?,
+                        Some(Expiration::EX(self.config.ttl_sec)),
+                        Some(SetOptions::XX),
+                        false,
+                    )
+                    .await?;
+                if refreshed.is_none() {
+                    return Ok(None);
+                }
+                // the index is only extended, it is not recreated either
+                self.redis
+                    .expire::<(), _>(
+                        format!("{}{}", SESSION_USER_KEY_PREFIX, session.user.user_id),
+                        self.config.ttl_sec,
+                    )
+                    .await?;
+                Ok(Some(session))
+            })
+        }
+
+        fn list(&self, user_id: i64) -> BoxFuture<'_, Result<Vec<Session>, anyhow::Error>> {
+            Box::pin(async move {
+                let user_key = format!("{}{}", SESSION_USER_KEY_PREFIX, user_id);
+                let ids: Vec<String> = self.redis.smembers(&user_key).await?;
+                let mut sessions = Vec::with_capacity(ids.len());
+                for id in ids {
+                    match self
+                        .redis
+                        .get::<Option<String>, _>(format!("{}{}", SESSION_KEY_PREFIX, id))
+                        .await?
+                    {
+                        Some(session) => sessions.push(serde_json::from_str(&session)?),
+                        // expired
+                        None => self.redis.srem::<(), _, _>(&user_key, id).await?,
+                    }
+                }
+                Ok(sessions)
+            })
+        }
+
+        fn terminate<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<(), anyhow::Error>> {
+            Box::pin(async move {
+                let key = format!("{}{}", SESSION_KEY_PREFIX, id);
+                if let Some(session) = self.redis.get::<Option<String>, _>(&key).await? {
+                    let session: Session = serde_json::from_str(&session)?;
+                    self.redis
+                        .srem::<(), _, _>(
+                            format!("{}{}", SESSION_USER_KEY_PREFIX, session.user.user_id),
+                            id,
+                        )
+                        .await?;
+                }
+                self.redis.del::<(), _>(key).await?;
+                Ok(())
+            })
+        }
+
+        fn terminate_all(&self, user_id: i64) -> BoxFuture<'_, Result<(), anyhow::Error>> {
+            Box::pin(async move {
+                let user_key = format!("{}{}", SESSION_USER_KEY_PREFIX, user_id);
+                let ids: Vec<String> = self.redis.smembers(&user_key).await?;
+                let mut keys: Vec<String> = ids
+                    .iter()
+                    .map(|id| format!("{}{}", SESSION_KEY_PREFIX, id))
+                    .collect();
+                keys.push(user_key);
+                self.redis.del::<(), _>(keys).await?;
+                Ok(())
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_in_memory_session_store() {
+        let store =
+            InMemorySessionStore::new(SessionConfigBuilder::default().capacity(2).build().unwrap());
+        let user = |user_id| TokenUser {
+            user_id,
+            ..Default::default()
+        };
+        let (first, _) = store.create(user(1)).await.unwrap();
+        let (second, _) = store.create(user(1)).await.unwrap();
+        assert_eq!(store.list(1).await.unwrap().len(), 2);
+        // touch the first one so that the second one is evicted
+        assert!(store.resolve(&first).await.unwrap().is_some());
+        let (third, _) = store.create(user(2)).await.unwrap();
+        assert!(store.resolve(&second).await.unwrap().is_none());
+        assert_eq!(store.list(1).await.unwrap().len(), 1);
+
+        store.terminate(&session_id(&third)).await.unwrap();
+        assert!(store.resolve(&third).await.unwrap().is_none());
+        store.terminate_all(1).await.unwrap();
+        assert!(store.resolve(&first).await.unwrap().is_none());
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_redis_session_store() {
+        let store = RedisSessionStore::new(
+            SessionConfigBuilder::default().build().unwrap(),
+            crate::redis::tests::test_pool().await,
+        );
+        let user_id = chrono::Utc::now().timestamp_micros();
+        let (key, session) = store
+            .create(TokenUser {
+                user_id,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert!(store.resolve(&key).await.unwrap().is_some());
+        store.terminate(&session.id).await.unwrap();
+        assert!(store.resolve(&key).await.unwrap().is_none());
+        assert!(store.list(user_id).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,336 @@
+use derive_builder::Builder;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+
+#[derive(Debug, Clone, Deserialize, Builder)]
+pub struct RateLimitConfig {
+    /// signs `X-Rate-Limit-Forward` keys, each of them bypasses limiters once
+    pub forward_key_secret: String,
+    #[builder(default)]
+    #[serde(default)]
+    pub limiters: Vec<RateLimiter>,
+    #[builder(default)]
+    #[serde(default)]
+    pub store: RateLimitStoreConfig,
+    #[builder(default)]
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+}
+
+/// stops calling a failing store, rules then follow their `on_failure` policy
+#[derive(Debug, Clone, Deserialize, Builder)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    /// consecutive errors opening the circuit
+    #[builder(default = "5")]
+    pub failure_threshold: u32,
+    /// the store is tried again after it
+    #[builder(default = "30")]
+    pub cooldown_sec: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            cooldown_sec: 30,
+        }
+    }
+}
+
+/// where counters live
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RateLimitStoreConfig {
+    /// per instance, for single instance services
+    Memory(MemoryStoreConfig),
+    /// shared by instances
+    Redis(RedisConfig),
+}
+
+impl Default for RateLimitStoreConfig {
+    fn default() -> Self {
+        Self::Memory(MemoryStoreConfig::default())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Builder)]
+#[serde(default)]
+pub struct MemoryStoreConfig {
+    /// keys are spread over shards, each one locked on its own
+    #[builder(default = "16")]
+    pub shards: usize,
+    /// idle keys are dropped at most this often
+    #[builder(default = "60")]
+    pub cleanup_interval_sec: i64,
+}
+
+impl Default for MemoryStoreConfig {
+    fn default() -> Self {
+        Self {
+            shards: 16,
+            cleanup_interval_sec: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Builder)]
+pub struct RateLimiter {
+    /// names the quota in keys, `route`, `pattern` or `path` if absent
+    #[builder(default)]
+    #[serde(default)]
+    pub name: Option<String>,
+    /// requests whose path starts with it are limited, any path if empty
+    #[builder(default)]
+    #[serde(default)]
+    pub path: String,
+    /// only requests of exactly `path` are limited
+    #[builder(default)]
+    #[serde(default)]
+    pub strict: bool,
+    /// axum route template, e.g. `/users/:id/orders`.
+    /// `MatchedPath` is only known to layers added by `Router::layer` or `route_layer`.
+    #[builder(default)]
+    #[serde(default)]
+    pub route: Option<String>,
+    /// matched against the request path
+    #[builder(default)]
+    #[serde(default)]
+    pub pattern: Option<Pattern>,
+    /// any method if empty
+    #[builder(default)]
+    #[serde(default)]
+    pub methods: Vec<String>,
+    /// each client ip has its own quota, same as `keys: [ip]`
+    #[builder(default)]
+    #[serde(default)]
+    pub scope_ip: bool,
+    /// callers sharing a value of every key share a quota
+    #[builder(default)]
+    #[serde(default)]
+    pub keys: Vec<RateLimitKey>,
+    pub interval_sec: u64,
+    /// requests allowed per `interval_sec`
+    pub permits: u64,
+    #[builder(default)]
+    #[serde(default)]
+    pub algorithm: RateLimitAlgorithm,
+    /// requests allowed at once by `TokenBucket` and `Gcra`, `permits` if absent
+    #[builder(default)]
+    #[serde(default)]
+    pub burst: Option<u64>,
+    /// what to do while the store is unavailable
+    #[builder(default)]
+    #[serde(default)]
+    pub on_failure: RateLimitFailurePolicy,
+}
+
+impl RateLimiter {
+    pub fn capacity(&self) -> u64 {
+        self.burst.unwrap_or(self.permits).max(1)
+    }
+
+    /// requests matching the rule share quotas named by it
+    pub fn id(&self) -> String {
+        let id = self
+            .name
+            .as_deref()
+            .or(self.route.as_deref())
+            .or(self.pattern.as_ref().map(|p| p.0.as_str()))
+            .unwrap_or(&self.path);
+        if self.methods.is_empty() {
+            id.to_owned()
+        } else {
+            format!("{}:{}", id, self.methods.join(","))
+        }
+    }
+}
+
+/// regex compiled once, when the config is loaded
+#[derive(Debug, Clone)]
+pub struct Pattern(pub Regex);
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        Ok(Self(Regex::new(pattern)?))
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let pattern = String::deserialize(deserializer)?;
+        Pattern::new(&pattern).map_err(serde::de::Error::custom)
+    }
+}
+
+/// key dimensions missing from a request, e.g. the user of anonymous callers, fall back to the ip
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RateLimitKey {
+    Ip,
+    /// `TokenUser.user_id`
+    User,
+    /// fingerprint of the api key, see `ApiKeyFingerprint`
+    ApiKey,
+    /// the whole tenant of the authenticated user shares the quota
+    Tenant,
+    Header {
+        name: String,
+    },
+    /// an extractor registered on the middleware under `name`
+    Custom {
+        name: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitAlgorithm {
+    /// exact, keeps a timestamp per permitted request
+    #[default]
+    SlidingLog,
+    /// weights the count of the previous window by its overlap with the sliding one
+    SlidingWindow,
+    /// a counter reset every `interval_sec` after the first request
+    FixedWindow,
+    /// refilled by `permits` per `interval_sec`, holding up to `burst` tokens
+    TokenBucket,
+    /// generic cell rate algorithm, a token bucket holding a single timestamp
+    Gcra,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitFailurePolicy {
+    /// requests are rejected
+    #[default]
+    Closed,
+    /// requests are not limited by the rule
+    Open,
+    /// the rule is enforced by each instance on its own
+    Local,
+}
+
+/// caps requests in flight, requests over the caps wait for `queue_timeout_ms` then are shed
+#[derive(Debug, Clone, Deserialize, Builder)]
+pub struct ConcurrencyLimitConfig {
+    /// requests in flight of the whole service, unlimited if 0
+    #[builder(default)]
+    #[serde(default)]
+    pub max_in_flight: usize,
+    /// 0 sheds at once
+    #[builder(default = "100")]
+    #[serde(default = "default_queue_timeout_ms")]
+    pub queue_timeout_ms: u64,
+    /// sent in `Retry-After` of shed requests
+    #[builder(default = "1")]
+    #[serde(default = "default_retry_after_sec")]
+    pub retry_after_sec: u64,
+    #[builder(default)]
+    #[serde(default)]
+    pub limiters: Vec<ConcurrencyLimiter>,
+    /// adjusts `max_in_flight` to the observed latency
+    #[builder(default)]
+    #[serde(default)]
+    pub adaptive: Option<AdaptiveLimitConfig>,
+}
+
+fn default_queue_timeout_ms() -> u64 {
+    100
+}
+
+fn default_retry_after_sec() -> u64 {
+    1
+}
+
+/// requests matching it share `max_in_flight`, requests of each value of `keys` if given
+#[derive(Debug, Clone, Deserialize, Builder)]
+pub struct ConcurrencyLimiter {
+    /// requests whose path starts with it are limited, any path if empty
+    #[builder(default)]
+    #[serde(default)]
+    pub path: String,
+    /// axum route template, see `RateLimiter.route`
+    #[builder(default)]
+    #[serde(default)]
+    pub route: Option<String>,
+    /// any method if empty
+    #[builder(default)]
+    #[serde(default)]
+    pub methods: Vec<String>,
+    #[builder(default)]
+    #[serde(default)]
+    pub keys: Vec<RateLimitKey>,
+    pub max_in_flight: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Builder)]
+#[serde(default)]
+pub struct AdaptiveLimitConfig {
+    #[builder(default)]
+    pub algorithm: AdaptiveLimitAlgorithm,
+    #[builder(default = "1")]
+    pub min_limit: usize,
+    /// `max_in_flight` if 0
+    #[builder(default)]
+    pub max_limit: usize,
+    /// slower or failed requests decrease the limit of `Aimd`
+    #[builder(default = "1000")]
+    pub latency_threshold_ms: u64,
+    /// the limit of `Aimd` is multiplied by it on decrease
+    #[builder(default = "0.9")]
+    pub backoff_ratio: f64,
+    /// weight of each sample of `Gradient`
+    #[builder(default = "0.2")]
+    pub smoothing: f64,
+}
+
+impl Default for AdaptiveLimitConfig {
+    fn default() -> Self {
+        Self {
+            algorithm: AdaptiveLimitAlgorithm::default(),
+            min_limit: 1,
+            max_limit: 0,
+            latency_threshold_ms: 1000,
+            backoff_ratio: 0.9,
+            smoothing: 0.2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AdaptiveLimitAlgorithm {
+    /// additive increase while saturated, multiplicative decrease on slow or failed requests
+    #[default]
+    Aimd,
+    /// scaled by the ratio of the lowest latency to the recent one
+    Gradient,
+}
+
+#[derive(Debug, Clone, Deserialize, Builder)]
+pub struct RedisConfig {
+    /// e.g. `redis://127.0.0.1:6379/0`, `rediss://` for tls
+    pub url: String,
+    #[builder(default = "4")]
+    #[serde(default = "default_pool_size")]
+    pub pool_size: usize,
+    #[builder(default = "5")]
+    #[serde(default = "default_connect_timeout_sec")]
+    pub connect_timeout_sec: u64,
+    /// 0 retries forever
+    #[builder(default)]
+    #[serde(default)]
+    pub max_reconnect_attempts: u32,
+}
+
+fn default_pool_size() -> usize {
+    4
+}
+
+fn default_connect_timeout_sec() -> u64 {
+    5
+}
@@ -0,0 +1,74 @@
+use super::{AuthResult, Authenticator, AUTH_METHOD_KEY_API_KEY};
+use crate::http::header;
+use crate::http::user_token::TokenUser;
+use crate::utils::hash::signing_none_secret;
+use anyhow::anyhow;
+use axum::http::request::Parts;
+use futures_util::future::BoxFuture;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub trait ApiKeyVerifier: Send + Sync {
+    /// `Ok(None)` if the key is unknown
+    fn verify<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> BoxFuture<'a, Result<Option<TokenUser>, anyhow::Error>>;
+}
+
+/// static keys. map key is the sha256 hex of api key, never the raw key
+impl ApiKeyVerifier for HashMap<String, TokenUser> {
+    fn verify<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> BoxFuture<'a, Result<Option<TokenUser>, anyhow::Error>> {
+        Box::pin(async move { Ok(self.get(&signing_none_secret(key)).cloned()) })
+    }
+}
+
+pub const API_KEY_AUTHENTICATOR: &str = "api_key";
+
+/// sha256 hex of the api key a request was authenticated with.
+/// inserted by the authentication chain, which strips the key itself.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ApiKeyFingerprint(pub String);
+
+impl ApiKeyFingerprint {
+    pub fn new(key: &str) -> Self {
+        Self(signing_none_secret(key))
+    }
+}
+
+/// `X-Access-ID: <api key>`
+pub struct ApiKeyAuthenticator {
+    verifier: Arc<dyn ApiKeyVerifier>,
+}
+
+impl ApiKeyAuthenticator {
+    pub fn new(verifier: Arc<dyn ApiKeyVerifier>) -> Self {
+        Self { verifier }
+    }
+}
+
+impl Authenticator for ApiKeyAuthenticator {
+    fn name(&self) -> &'static str {
+        API_KEY_AUTHENTICATOR
+    }
+
+    fn scheme(&self) -> &'static str {
+        AUTH_METHOD_KEY_API_KEY
+    }
+
+    fn authenticate<'a>(&'a self, parts: &'a Parts) -> BoxFuture<'a, AuthResult> {
+        Box::pin(async move {
+            let Some(key) = parts.headers.get(header::X_ACCESS_ID) else {
+                return Ok(None);
+            };
+            let key = key.to_str()?;
+            match self.verifier.verify(key).await? {
+                Some(token_user) => Ok(Some(token_user)),
+                None => Err(anyhow!("unknown api key")),
+            }
+        })
+    }
+}
@@ -0,0 +1,86 @@
+use super::{AuthResult, Authenticator, AUTH_METHOD_KEY_BASIC};
+use crate::http::user_token::TokenUser;
+use anyhow::anyhow;
+use axum::http::request::Parts;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// verified for unknown users, they take as long to reject as wrong passwords
+const DUMMY_PASSWORD_HASH: &str = "$2b$12$QTMvJ0mepvkgzUAL52vj3uBEx.8X8RHsc9F7MXptZr24/URDyiaxm";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BasicCredential {
+    /// bcrypt hash
+    pub password_hash: String,
+    pub user: TokenUser,
+}
+
+pub trait BasicCredentialProvider: Send + Sync {
+    fn find<'a>(
+        &'a self,
+        username: &'a str,
+    ) -> BoxFuture<'a, Result<Option<BasicCredential>, anyhow::Error>>;
+}
+
+impl BasicCredentialProvider for HashMap<String, BasicCredential> {
+    fn find<'a>(
+        &'a self,
+        username: &'a str,
+    ) -> BoxFuture<'a, Result<Option<BasicCredential>, anyhow::Error>> {
+        Box::pin(async move { Ok(self.get(username).cloned()) })
+    }
+}
+
+/// `Authorization: Basic base64(username:password)`
+pub struct BasicAuthenticator {
+    provider: Arc<dyn BasicCredentialProvider>,
+}
+
+impl BasicAuthenticator {
+    pub fn new(provider: Arc<dyn BasicCredentialProvider>) -> Self {
+        Self { provider }
+    }
+}
+
+pub(crate) fn decode_basic(encoded: &str) -> Result<(String, String), anyhow::Error> {
+    let decoded = String::from_utf8(STANDARD.decode(encoded)?)?;
+    let (username, password) = decoded
+        .split_once(':')
+        .ok_or(anyhow!("malformed basic credentials"))?;
+    Ok((username.to_owned(), password.to_owned()))
+}
+
+impl Authenticator for BasicAuthenticator {
+    fn name(&self) -> &'static str {
+        "basic"
+    }
+
+    fn scheme(&self) -> &'static str {
+        AUTH_METHOD_KEY_BASIC
+    }
+
+    fn authenticate<'a>(&'a self, parts: &'a Parts) -> BoxFuture<'a, AuthResult> {
+        Box::pin(async move {
+            let Some((AUTH_METHOD_KEY_BASIC, encoded)) =
+                super::extract_authorization(&parts.headers)
+            else {
+                return Ok(None);
+            };
+            let (username, password) = decode_basic(encoded)?;
+            let credential = self.provider.find(&username).await?;
+            // bcrypt is cpu bound
+            let verified = tokio::task::spawn_blocking(move || match credential {
+                Some(credential) => bcrypt::verify(password, &credential.password_hash)
+                    .map(|ok| ok.then_some(credential.user)),
+                None => bcrypt::verify(password, DUMMY_PASSWORD_HASH).map(|_| None),
+            })
+            .await??;
+            verified
+                .map(Some)
+                .ok_or_else(|| anyhow!("invalid credentials"))
+        })
+    }
+}
@@ -0,0 +1,126 @@
+use super::{AUTH_METHOD_KEY_BASIC, AUTH_METHOD_KEY_JWT};
+use crate::utils::http_error_handler::ErrorResponse;
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use tracing::error;
+
+pub const DEFAULT_REALM: &str = "api";
+
+/// error codes of RFC 6750
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BearerError {
+    InvalidRequest,
+    InvalidToken,
+    InsufficientScope,
+}
+
+impl BearerError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InvalidRequest => "invalid_request",
+            Self::InvalidToken => "invalid_token",
+            Self::InsufficientScope => "insufficient_scope",
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidRequest => StatusCode::BAD_REQUEST,
+            Self::InvalidToken => StatusCode::UNAUTHORIZED,
+            Self::InsufficientScope => StatusCode::FORBIDDEN,
+        }
+    }
+}
+
+/// `WWW-Authenticate` challenge along with the error envelope.
+/// the body carries the same `error` code as the header.
+#[derive(Debug, Clone)]
+pub struct Challenge {
+    scheme: &'static str,
+    realm: String,
+    error: Option<BearerError>,
+    description: Option<String>,
+    scope: Option<String>,
+}
+
+// quoted-string of RFC 7230, `error_description` must not contain `"` and `\`
+fn quote(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| *c != '"' && *c != '\\' && (*c == ' ' || c.is_ascii_graphic()))
+        .collect()
+}
+
+impl Challenge {
+    pub fn new(scheme: &'static str, realm: &str) -> Self {
+        Self {
+            scheme,
+            realm: realm.to_owned(),
+            error: None,
+            description: None,
+            scope: None,
+        }
+    }
+
+    pub fn bearer(realm: &str) -> Self {
+        Self::new(AUTH_METHOD_KEY_JWT, realm)
+    }
+
+    pub fn basic(realm: &str) -> Self {
+        Self::new(AUTH_METHOD_KEY_BASIC, realm)
+    }
+
+    pub fn error(mut self, error: BearerError, description: &str) -> Self {
+        self.error = Some(error);
+        self.description = Some(description.to_owned());
+        self
+    }
+
+    /// scopes required, space separated
+    pub fn scope(mut self, scope: &str) -> Self {
+        self.scope = Some(scope.to_owned());
+        self
+    }
+
+    pub fn to_header_value(&self) -> String {
+        let mut params = vec![format!("realm=\"{}\"", quote(&self.realm))];
+        // only bearer defines error parameters
+        if self.scheme == AUTH_METHOD_KEY_JWT {
+            if let Some(error) = self.error {
+                params.push(format!("error=\"{}\"", error.as_str()));
+            }
+            if let Some(description) = &self.description {
+                params.push(format!("error_description=\"{}\"", quote(description)));
+            }
+            if let Some(scope) = &self.scope {
+                params.push(format!("scope=\"{}\"", quote(scope)));
+            }
+        } else if self.scheme == AUTH_METHOD_KEY_BASIC {
+            params.push("charset=\"UTF-8\"".to_owned());
+        }
+        format!("{} {}", self.scheme, params.join(", "))
+    }
+}
+
+impl IntoResponse for Challenge {
+    fn into_response(self) -> Response {
+        let mut response = match self.error {
+            Some(error) => ErrorResponse::new_with_error(
+                error.status_code(),
+                error.as_str(),
+                self.description.clone(),
+            ),
+            None => ErrorResponse::new_no_auth(),
+        }
+        .into_response();
+        match HeaderValue::from_str(&self.to_header_value()) {
+            Ok(challenge) => {
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, challenge);
+            }
+            Err(e) => error!(err = e.to_string(), "build www-authenticate header error"),
+        }
+        response
+    }
+}
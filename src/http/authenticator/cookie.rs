@@ -0,0 +1,50 @@
+use super::{AuthResult, Authenticator};
+use crate::auth::session::SessionStore;
+use crate::http::cookie;
+use crate::http::middlewares::jwt_authentication::JwtAuthConfig;
+use anyhow::anyhow;
+use axum::http::request::Parts;
+use futures_util::future::BoxFuture;
+use std::sync::Arc;
+
+pub enum CookieCredential {
+    Jwt(JwtAuthConfig),
+    SessionKey(Arc<dyn SessionStore>),
+}
+
+/// the jwt or the session key is read from a cookie, so that browsers never expose it to js.
+/// should be used together with the csrf middleware.
+pub struct CookieAuthenticator {
+    cookie_name: String,
+    credential: CookieCredential,
+}
+
+impl CookieAuthenticator {
+    pub fn new(cookie_name: &str, credential: CookieCredential) -> Self {
+        Self {
+            cookie_name: cookie_name.to_owned(),
+            credential,
+        }
+    }
+}
+
+impl Authenticator for CookieAuthenticator {
+    fn name(&self) -> &'static str {
+        "cookie"
+    }
+
+    fn authenticate<'a>(&'a self, parts: &'a Parts) -> BoxFuture<'a, AuthResult> {
+        Box::pin(async move {
+            let Some(value) = cookie::get_cookie(&parts.headers, &self.cookie_name) else {
+                return Ok(None);
+            };
+            match &self.credential {
+                CookieCredential::Jwt(config) => Ok(Some(config.parse_token_user(value)?)),
+                CookieCredential::SessionKey(store) => match store.resolve(value).await? {
+                    Some(session) => Ok(Some(session.user)),
+                    None => Err(anyhow!("unknown or expired session")),
+                },
+            }
+        })
+    }
+}
@@ -0,0 +1,32 @@
+use super::{AuthResult, Authenticator, AUTH_METHOD_KEY_JWT};
+use crate::http::middlewares::jwt_authentication::JwtAuthConfig;
+use axum::http::request::Parts;
+use futures_util::future::BoxFuture;
+
+/// `Authorization: Bearer <jwt>`
+pub struct JwtAuthenticator {
+    config: JwtAuthConfig,
+}
+
+impl JwtAuthenticator {
+    pub fn new(config: JwtAuthConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl Authenticator for JwtAuthenticator {
+    fn name(&self) -> &'static str {
+        "jwt"
+    }
+
+    fn authenticate<'a>(&'a self, parts: &'a Parts) -> BoxFuture<'a, AuthResult> {
+        Box::pin(async move {
+            match super::extract_authorization(&parts.headers) {
+                Some((AUTH_METHOD_KEY_JWT, token)) => {
+                    Ok(Some(self.config.parse_token_user(token)?))
+                }
+                _ => Ok(None),
+            }
+        })
+    }
+}
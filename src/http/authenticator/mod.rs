@@ -0,0 +1,50 @@
+use crate::http::user_token::TokenUser;
+use axum::http::{header, request::Parts, HeaderMap};
+use futures_util::future::BoxFuture;
+
+mod api_key;
+mod basic;
+mod challenge;
+mod cookie;
+mod jwt;
+mod session;
+
+pub use api_key::{ApiKeyAuthenticator, ApiKeyFingerprint, ApiKeyVerifier, API_KEY_AUTHENTICATOR};
+pub(crate) use basic::decode_basic;
+pub use basic::{BasicAuthenticator, BasicCredential, BasicCredentialProvider};
+pub use challenge::{BearerError, Challenge, DEFAULT_REALM};
+pub use cookie::{CookieAuthenticator, CookieCredential};
+pub use jwt::JwtAuthenticator;
+pub use session::SessionAuthenticator;
+
+pub const AUTH_METHOD_KEY_JWT: &str = "Bearer";
+pub const AUTH_METHOD_KEY_BASIC: &str = "Basic";
+/// scheme of api key challenges, the keys themselves are sent in `X-Access-ID`
+pub const AUTH_METHOD_KEY_API_KEY: &str = "ApiKey";
+
+/// `Ok(None)` means no credential this authenticator understands was found,
+/// the next authenticator of the chain will be tried.
+/// `Err` means a credential was presented but it is invalid.
+pub type AuthResult = Result<Option<TokenUser>, anyhow::Error>;
+
+pub trait Authenticator: Send + Sync {
+    /// used for logging
+    fn name(&self) -> &'static str;
+
+    /// scheme of the `WWW-Authenticate` challenge sent when the credential is rejected
+    fn scheme(&self) -> &'static str {
+        AUTH_METHOD_KEY_JWT
+    }
+
+    fn authenticate<'a>(&'a self, parts: &'a Parts) -> BoxFuture<'a, AuthResult>;
+}
+
+/// split `Authorization: <scheme> <credentials>`
+pub fn extract_authorization(headers: &HeaderMap) -> Option<(&str, &str)> {
+    let val = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let splits: Vec<&str> = val.split(' ').collect();
+    if splits.len() == 2 {
+        return Some((splits[0], splits[1]));
+    }
+    None
+}
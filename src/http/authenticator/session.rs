@@ -0,0 +1,36 @@
+use super::{AuthResult, Authenticator};
+use crate::auth::session::SessionStore;
+use crate::http::header;
+use anyhow::anyhow;
+use axum::http::request::Parts;
+use futures_util::future::BoxFuture;
+use std::sync::Arc;
+
+/// `X-Token-User-Cache-Key: <opaque session key>`
+pub struct SessionAuthenticator {
+    store: Arc<dyn SessionStore>,
+}
+
+impl SessionAuthenticator {
+    pub fn new(store: Arc<dyn SessionStore>) -> Self {
+        Self { store }
+    }
+}
+
+impl Authenticator for SessionAuthenticator {
+    fn name(&self) -> &'static str {
+        "session"
+    }
+
+    fn authenticate<'a>(&'a self, parts: &'a Parts) -> BoxFuture<'a, AuthResult> {
+        Box::pin(async move {
+            let Some(key) = parts.headers.get(header::X_TOKEN_USER_CACHE_KEY) else {
+                return Ok(None);
+            };
+            match self.store.resolve(key.to_str()?).await? {
+                Some(session) => Ok(Some(session.user)),
+                None => Err(anyhow!("unknown or expired session")),
+            }
+        })
+    }
+}
@@ -0,0 +1,102 @@
+use axum::http::{header, HeaderMap, HeaderValue};
+use derive_builder::Builder;
+use serde::Deserialize;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+#[derive(Debug, Clone, Deserialize, Builder)]
+pub struct CookieConfig {
+    pub name: String,
+    #[builder(default = "true")]
+    #[serde(default = "default_true")]
+    pub http_only: bool,
+    #[builder(default = "true")]
+    #[serde(default = "default_true")]
+    pub secure: bool,
+    #[builder(default = "SameSite::Lax")]
+    #[serde(default = "default_same_site")]
+    pub same_site: SameSite,
+    #[builder(default = "\"/\".to_owned()")]
+    #[serde(default = "default_path")]
+    pub path: String,
+    #[builder(default)]
+    #[serde(default)]
+    pub domain: Option<String>,
+    /// session cookie if absent
+    #[builder(default)]
+    #[serde(default)]
+    pub max_age_sec: Option<i64>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_same_site() -> SameSite {
+    SameSite::Lax
+}
+
+fn default_path() -> String {
+    "/".to_owned()
+}
+
+impl CookieConfig {
+    fn to_set_cookie(&self, value: &str, max_age_sec: Option<i64>) -> String {
+        let mut cookie = format!("{}={}; Path={}", self.name, value, self.path);
+        if let Some(domain) = &self.domain {
+            let _ = write!(cookie, "; Domain={}", domain);
+        }
+        if let Some(max_age_sec) = max_age_sec {
+            let _ = write!(cookie, "; Max-Age={}", max_age_sec);
+        }
+        if self.http_only {
+            cookie.push_str("; HttpOnly");
+        }
+        // `SameSite=None` is rejected by browsers without `Secure`
+        if self.secure || self.same_site == SameSite::None {
+            cookie.push_str("; Secure");
+        }
+        let _ = write!(cookie, "; SameSite={:?}", self.same_site);
+        cookie
+    }
+}
+
+/// value of the first cookie named `name` in `Cookie` headers
+pub fn get_cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|val| val.to_str().ok())
+        .flat_map(|val| val.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.trim_matches('"'))
+}
+
+/// appends `Set-Cookie` to response headers
+pub fn set_cookie(
+    headers: &mut HeaderMap,
+    config: &CookieConfig,
+    value: &str,
+) -> Result<(), anyhow::Error> {
+    headers.append(
+        header::SET_COOKIE,
+        HeaderValue::from_str(&config.to_set_cookie(value, config.max_age_sec))?,
+    );
+    Ok(())
+}
+
+/// appends an expired `Set-Cookie` to response headers
+pub fn clear_cookie(headers: &mut HeaderMap, config: &CookieConfig) -> Result<(), anyhow::Error> {
+    headers.append(
+        header::SET_COOKIE,
+        HeaderValue::from_str(&config.to_set_cookie("", Some(0)))?,
+    );
+    Ok(())
+}
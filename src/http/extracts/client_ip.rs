@@ -0,0 +1,32 @@
+use crate::utils::http_error_handler::ErrorResponse;
+use axum::async_trait;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use std::net::{IpAddr, SocketAddr};
+
+/// ip of the caller resolved by the `client_ip` middleware, the peer address without it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = ErrorResponse;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<ClientIp>()
+            .copied()
+            .or_else(|| {
+                parts
+                    .extensions
+                    .get::<ConnectInfo<SocketAddr>>()
+                    .map(|ConnectInfo(addr)| ClientIp(addr.ip()))
+            })
+            .ok_or_else(|| ErrorResponse::new_with_status_code(StatusCode::INTERNAL_SERVER_ERROR))
+    }
+}
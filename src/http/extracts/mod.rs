@@ -1,2 +1,2 @@
-
-
+pub mod client_ip;
+pub mod tenant;
@@ -2,6 +2,7 @@
 
 pub const X_REQUEST_ID: &str = "X-Request-ID";
 pub const X_REAL_IP: &str = "X-Real-IP";
+pub const X_FORWARDED_FOR: &str = "X-Forwarded-For";
 pub const X_ACCESS_ID: &str = "X-Access-ID";
 pub const X_ACCESS_ID_EXPR: &str = "X-Access-ID-Expr";
 pub const X_TOKEN_USER: &str = "X-Token-User";
@@ -9,4 +10,9 @@ pub const X_TOKEN_USER_CACHE_KEY: &str = "X-Token-User-Cache-Key";
 pub const X_OPEN_TOKEN: &str = "X-Open-Token";
 pub const X_USE_OPEN_TOKEN: &str = "X-Use-Open-Token";
 pub const X_RATE_LIMIT_FORWARD: &str = "X-Rate-Limit-Forward";
-
+pub const X_CSRF_TOKEN: &str = "X-CSRF-Token";
+pub const X_TENANT_ID: &str = "X-Tenant-ID";
+// draft-ietf-httpapi-ratelimit-headers
+pub const RATE_LIMIT_LIMIT: &str = "RateLimit-Limit";
+pub const RATE_LIMIT_REMAINING: &str = "RateLimit-Remaining";
+pub const RATE_LIMIT_RESET: &str = "RateLimit-Reset";
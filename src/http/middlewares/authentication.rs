@@ -0,0 +1,254 @@
+use crate::auth::impersonation;
+use crate::http::authenticator::{
+    ApiKeyFingerprint, Authenticator, BearerError, Challenge, API_KEY_AUTHENTICATOR, DEFAULT_REALM,
+};
+use crate::http::header as custom_header;
+use crate::http::middlewares::jwt_authentication::JwtValidationError;
+use crate::http::user_token::TokenUser;
+use axum::extract::Request;
+use axum::http::{header, request::Parts};
+use axum::response::{IntoResponse, Response};
+use futures_util::future::BoxFuture;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+use tracing::{debug, error};
+
+/// authenticators are tried in order, the first one recognizing its credential wins.
+/// requests without any credential are passed through without `TokenUser`.
+#[derive(Clone)]
+pub struct MLayer {
+    realm: Arc<str>,
+    authenticators: Arc<Vec<Arc<dyn Authenticator>>>,
+}
+
+pub fn new(authenticators: Vec<Arc<dyn Authenticator>>) -> MLayer {
+    new_with_realm(DEFAULT_REALM, authenticators)
+}
+
+pub fn new_with_realm(realm: &str, authenticators: Vec<Arc<dyn Authenticator>>) -> MLayer {
+    MLayer {
+        realm: realm.into(),
+        authenticators: Arc::new(authenticators),
+    }
+}
+
+impl<S> Layer<S> for MLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Middleware {
+            inner,
+            realm: self.realm.clone(),
+            authenticators: self.authenticators.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    inner: S,
+    realm: Arc<str>,
+    authenticators: Arc<Vec<Arc<dyn Authenticator>>>,
+}
+
+/// the name of the authenticator recognizing the credential is returned with its `TokenUser`.
+/// the challenge of the authenticator rejecting the credential is returned on error.
+async fn authenticate(
+    authenticators: &[Arc<dyn Authenticator>],
+    parts: &Parts,
+    realm: &str,
+) -> Result<Option<(&'static str, TokenUser)>, Challenge> {
+    for authenticator in authenticators {
+        match authenticator.authenticate(parts).await {
+            Ok(Some(token_user)) => {
+                debug!(authenticator = authenticator.name(), "authenticated");
+                return Ok(Some((authenticator.name(), token_user)));
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!(
+                    authenticator = authenticator.name(),
+                    err = e.to_string(),
+                    "authenticate error"
+                );
+                // details of other credentials are not told, e.g. whether a user exists
+                let description = e
+                    .downcast_ref::<JwtValidationError>()
+                    .map(|e| e.to_string())
+                    .unwrap_or("invalid credentials".to_owned());
+                return Err(Challenge::new(authenticator.scheme(), realm)
+                    .error(BearerError::InvalidToken, &description));
+            }
+        }
+    }
+    Ok(None)
+}
+
+impl<S> Service<Request> for Middleware<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let authenticators = self.authenticators.clone();
+        let realm = self.realm.clone();
+        // the ready service must be the one to be called
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            let (mut parts, body) = request.into_parts();
+            let mut impersonated = None;
+            match authenticate(&authenticators, &parts, &realm).await {
+                Ok(Some((name, token_user))) => {
+                    if name == API_KEY_AUTHENTICATOR {
+                        if let Some(key) = parts
+                            .headers
+                            .get(custom_header::X_ACCESS_ID)
+                            .and_then(|v| v.to_str().ok())
+                        {
+                            let fingerprint = ApiKeyFingerprint::new(key);
+                            parts.extensions.insert(fingerprint);
+                        }
+                    }
+                    if token_user.is_impersonated() {
+                        impersonated = Some(token_user.clone());
+                    }
+                    parts.extensions.insert(token_user);
+                }
+                Ok(None) => {}
+                Err(challenge) => return Ok(challenge.into_response()),
+            }
+            // credentials must not be leaked to upstream
+            parts.headers.remove(header::AUTHORIZATION);
+            parts.headers.remove(custom_header::X_ACCESS_ID);
+            parts.headers.remove(custom_header::X_TOKEN_USER_CACHE_KEY);
+            let method = parts.method.clone();
+            let path = parts.uri.path().to_owned();
+            let ip = super::extract_ip(&parts.extensions);
+            let response = inner.call(Request::from_parts(parts, body)).await?;
+            if let Some(token_user) = impersonated {
+                impersonation::audit(&token_user, &ip, &method, &path, response.status());
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::authenticator::{
+        ApiKeyAuthenticator, BasicAuthenticator, BasicCredential, JwtAuthenticator,
+    };
+    use crate::http::middlewares::jwt_authentication::JwtAuthConfigBuilder;
+    use crate::http::user_token::TokenUser;
+    use crate::utils::hash::signing_none_secret;
+    use axum::http::StatusCode;
+    use axum::{routing::get, Extension, Router};
+    use std::collections::HashMap;
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        let api_keys = HashMap::from([(
+            signing_none_secret("my-api-key"),
+            TokenUser {
+                user_id: 1,
+                ..Default::default()
+            },
+        )]);
+        let basic = HashMap::from([(
+            "admin".to_owned(),
+            BasicCredential {
+                password_hash: bcrypt::hash("passwd", 4).unwrap(),
+                user: TokenUser {
+                    user_id: 2,
+                    ..Default::default()
+                },
+            },
+        )]);
+        let jwt = JwtAuthConfigBuilder::default()
+            .issuer("test".to_owned())
+            .secret("secret".to_owned())
+            .build()
+            .unwrap();
+        Router::new()
+            .route(
+                "/",
+                get(|user: Option<Extension<TokenUser>>| async move {
+                    user.map(|u| u.user_id.to_string()).unwrap_or_default()
+                }),
+            )
+            .layer(new(vec![
+                Arc::new(JwtAuthenticator::new(jwt)),
+                Arc::new(ApiKeyAuthenticator::new(Arc::new(api_keys))),
+                Arc::new(BasicAuthenticator::new(Arc::new(basic))),
+            ]))
+    }
+
+    async fn call(name: &str, value: &str) -> (StatusCode, String) {
+        let (status, _, body) = call_with_challenge(name, value).await;
+        (status, body)
+    }
+
+    async fn call_with_challenge(name: &str, value: &str) -> (StatusCode, String, String) {
+        let response = app()
+            .oneshot(
+                Request::get("/")
+                    .header(name, value)
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let challenge = response
+            .headers()
+            .get(header::WWW_AUTHENTICATE)
+            .map(|v| v.to_str().unwrap().to_owned())
+            .unwrap_or_default();
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        (status, challenge, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_authenticator_chain() {
+        assert_eq!(
+            call(custom_header::X_ACCESS_ID, "my-api-key").await,
+            (StatusCode::OK, "1".to_owned())
+        );
+        // admin:passwd
+        assert_eq!(
+            call("authorization", "Basic YWRtaW46cGFzc3dk").await,
+            (StatusCode::OK, "2".to_owned())
+        );
+        // rfc 6750 error parameters are only sent with bearer challenges
+        let (status, challenge, _) =
+            call_with_challenge(custom_header::X_ACCESS_ID, "bad-key").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(challenge, "ApiKey realm=\"api\"");
+        let (status, challenge, body) =
+            call_with_challenge("authorization", "Bearer bad.jwt.token").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(challenge.starts_with("Bearer realm=\"api\", error=\"invalid_token\""));
+        assert!(body.contains("\"error\":\"invalid_token\""));
+        let (_, challenge, _) =
+            call_with_challenge("authorization", "Basic YWRtaW46b3RoZXI=").await;
+        assert!(challenge.starts_with("Basic realm=\"api\""));
+        assert_eq!(
+            call("x-other", "any").await,
+            (StatusCode::OK, String::new())
+        );
+    }
+}
@@ -0,0 +1,249 @@
+use crate::http::extracts::client_ip::ClientIp;
+use crate::http::header;
+use axum::extract::{ConnectInfo, Request};
+use axum::http::{header::FORWARDED, HeaderMap};
+use axum::response::Response;
+use derive_builder::Builder;
+use futures_util::future::BoxFuture;
+use ipnet::IpNet;
+use serde::Deserialize;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+use tracing::debug;
+
+#[derive(Debug, Clone, Default, Deserialize, Builder)]
+pub struct ClientIpConfig {
+    /// forwarding headers are only read from peers in these networks, e.g. `10.0.0.0/8`
+    #[builder(default)]
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpNet>,
+    /// the header set by the trusted proxies, others are passed through by them unchecked
+    #[builder(default)]
+    #[serde(default)]
+    pub header: ForwardedHeader,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ForwardedHeader {
+    #[default]
+    XForwardedFor,
+    /// RFC 7239
+    Forwarded,
+    XRealIp,
+}
+
+impl ClientIpConfig {
+    fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|net| net.contains(ip))
+    }
+
+    /// walks the forwarding chain from the peer, the first untrusted hop is the client
+    pub fn resolve(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        // dual stack listeners see ipv4 peers as `::ffff:a.b.c.d`
+        let peer = peer.to_canonical();
+        if !self.is_trusted(&peer) {
+            return peer;
+        }
+        let chain = match self.header {
+            ForwardedHeader::XForwardedFor => header_values(headers, header::X_FORWARDED_FOR),
+            ForwardedHeader::Forwarded => forwarded_for(headers),
+            ForwardedHeader::XRealIp => header_values(headers, header::X_REAL_IP),
+        }
+        .unwrap_or_default();
+        let mut client = peer;
+        for hop in chain.iter().rev() {
+            // obfuscated or unknown hops can not be trusted further
+            let Some(ip) = parse_node(hop) else {
+                break;
+            };
+            client = ip;
+            if !self.is_trusted(&ip) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// comma separated values of every `name` header, `None` if absent
+fn header_values(headers: &HeaderMap, name: &str) -> Option<Vec<String>> {
+    let values = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|v| v.trim().to_owned())
+        .filter(|v| !v.is_empty())
+        .collect::<Vec<_>>();
+    (!values.is_empty()).then_some(values)
+}
+
+/// `for` parameters of RFC 7239 `Forwarded`, e.g. `for=192.0.2.60;proto=http, for="[2001:db8::1]:4711"`
+fn forwarded_for(headers: &HeaderMap) -> Option<Vec<String>> {
+    let elements = header_values(headers, FORWARDED.as_str())?;
+    let nodes = elements
+        .iter()
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                .map(|(_, node)| node.trim().trim_matches('"').to_owned())
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+    Some(nodes)
+}
+
+/// an ip, with an optional port and brackets around ipv6
+fn parse_node(node: &str) -> Option<IpAddr> {
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            node.strip_prefix('[')
+                .and_then(|node| node.strip_suffix(']'))
+                .and_then(|node| node.parse().ok())
+        })
+        .map(|ip| ip.to_canonical())
+}
+
+/// resolves `ClientIp`, requires `into_make_service_with_connect_info::<SocketAddr>`
+#[derive(Clone)]
+pub struct MLayer {
+    config: Arc<ClientIpConfig>,
+}
+
+pub fn new(config: ClientIpConfig) -> MLayer {
+    MLayer {
+        config: Arc::new(config),
+    }
+}
+
+impl<S> Layer<S> for MLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Middleware {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    inner: S,
+    config: Arc<ClientIpConfig>,
+}
+
+impl<S> Service<Request> for Middleware<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        let peer = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        if let Some(peer) = peer {
+            let ip = self.config.resolve(peer, request.headers());
+            debug!(peer = %peer, client_ip = %ip, "client ip resolved");
+            request.extensions_mut().insert(ClientIp(ip));
+        }
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let response: Response = future.await?;
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(
+                axum::http::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    fn test_resolve() {
+        let config = ClientIpConfigBuilder::default()
+            .trusted_proxies(vec![
+                "10.0.0.0/8".parse().unwrap(),
+                "::1/128".parse().unwrap(),
+            ])
+            .build()
+            .unwrap();
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+        let spoofed = headers(&[(header::X_FORWARDED_FOR, "1.1.1.1")]);
+        // untrusted peers can not forward
+        assert_eq!(config.resolve(ip("8.8.8.8"), &spoofed), ip("8.8.8.8"));
+        assert_eq!(config.resolve(ip("10.0.0.1"), &spoofed), ip("1.1.1.1"));
+        // spoofed hops before the first untrusted one are ignored
+        let chain = headers(&[
+            (header::X_FORWARDED_FOR, "1.1.1.1, 2.2.2.2"),
+            (header::X_FORWARDED_FOR, "10.0.0.2"),
+        ]);
+        assert_eq!(config.resolve(ip("10.0.0.1"), &chain), ip("2.2.2.2"));
+        let forwarded = headers(&[
+            (
+                FORWARDED.as_str(),
+                r#"for=1.1.1.1, for="[2001:db8::1]:4711";proto=https"#,
+            ),
+            (header::X_FORWARDED_FOR, "3.3.3.3"),
+            (header::X_REAL_IP, "4.4.4.4"),
+        ]);
+        // headers other than the configured one are forged by callers
+        assert_eq!(config.resolve(ip("::1"), &forwarded), ip("3.3.3.3"));
+        let forged = headers(&[(FORWARDED.as_str(), "for=192.168.0.1")]);
+        assert_eq!(config.resolve(ip("10.0.0.1"), &forged), ip("10.0.0.1"));
+
+        let config = ClientIpConfig {
+            header: ForwardedHeader::Forwarded,
+            ..config
+        };
+        assert_eq!(config.resolve(ip("::1"), &forwarded), ip("2001:db8::1"));
+        let hidden = headers(&[(FORWARDED.as_str(), "for=1.1.1.1, for=_hidden")]);
+        assert_eq!(config.resolve(ip("10.0.0.1"), &hidden), ip("10.0.0.1"));
+
+        let config = ClientIpConfig {
+            header: ForwardedHeader::XRealIp,
+            ..config
+        };
+        assert_eq!(config.resolve(ip("::1"), &forwarded), ip("4.4.4.4"));
+    }
+
+    #[test]
+    fn test_resolve_ipv4_mapped() {
+        let config = ClientIpConfigBuilder::default()
+            .trusted_proxies(vec!["10.0.0.0/8".parse().unwrap()])
+            .build()
+            .unwrap();
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+        let chain = headers(&[(header::X_FORWARDED_FOR, "1.1.1.1, ::ffff:10.0.0.2")]);
+        assert_eq!(config.resolve(ip("::ffff:10.0.0.1"), &chain), ip("1.1.1.1"));
+        assert_eq!(config.resolve(ip("::ffff:8.8.8.8"), &chain), ip("8.8.8.8"));
+    }
+}
@@ -0,0 +1,264 @@
+use super::rate_limiter::{key_dimension, KeyExtractor};
+use crate::config::{ConcurrencyLimitConfig, ConcurrencyLimiter};
+use crate::rate_limit::ConcurrencyGate;
+use crate::utils::http_error_handler::ErrorResponse;
+use axum::extract::{MatchedPath, Request};
+use axum::http::{header::RETRY_AFTER, Method, StatusCode};
+use axum::response::{IntoResponse, Response};
+use futures_util::future::BoxFuture;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tower::{Layer, Service};
+use tracing::{info, warn};
+
+/// gates of idle keys are dropped once there are more of them
+const MAX_IDLE_GATES: usize = 1024;
+
+/// a `ConcurrencyLimiter` with a gate per key
+struct Rule {
+    limiter: ConcurrencyLimiter,
+    methods: Vec<Method>,
+    gates: Mutex<HashMap<String, Arc<ConcurrencyGate>>>,
+}
+
+impl Rule {
+    fn new(limiter: &ConcurrencyLimiter) -> Self {
+        let methods = limiter
+            .methods
+            .iter()
+            .filter_map(|method| {
+                Method::from_bytes(method.to_uppercase().as_bytes())
+                    .inspect_err(|_| warn!(method = method, "invalid concurrency limit method"))
+                    .ok()
+            })
+            .collect();
+        Self {
+            limiter: limiter.clone(),
+            methods,
+            gates: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn matches(&self, request: &Request) -> bool {
+        if !self.methods.is_empty() && !self.methods.contains(request.method()) {
+            return false;
+        }
+        if !request.uri().path().starts_with(&self.limiter.path) {
+            return false;
+        }
+        self.limiter.route.as_ref().is_none_or(|route| {
+            request
+                .extensions()
+                .get::<MatchedPath>()
+                .is_some_and(|matched| matched.as_str() == route)
+        })
+    }
+
+    fn gate(&self, key: String) -> Arc<ConcurrencyGate> {
+        let mut gates = self.gates.lock().unwrap_or_else(PoisonError::into_inner);
+        if gates.len() > MAX_IDLE_GATES {
+            // gates in use are shared with their permits
+            gates.retain(|_, gate| Arc::strong_count(gate) > 1);
+        }
+        gates
+            .entry(key)
+            .or_insert_with(|| Arc::new(ConcurrencyGate::new(self.limiter.max_in_flight)))
+            .clone()
+    }
+}
+
+#[derive(Clone)]
+pub struct MLayer {
+    config: Arc<ConcurrencyLimitConfig>,
+    global: Option<Arc<ConcurrencyGate>>,
+    rules: Arc<Vec<Rule>>,
+    extractors: HashMap<String, KeyExtractor>,
+}
+
+pub fn new(config: ConcurrencyLimitConfig) -> MLayer {
+    let global = (config.max_in_flight > 0).then(|| {
+        Arc::new(ConcurrencyGate::new_adaptive(
+            config.max_in_flight,
+            config.adaptive.clone(),
+        ))
+    });
+    MLayer {
+        global,
+        rules: Arc::new(config.limiters.iter().map(Rule::new).collect()),
+        config: Arc::new(config),
+        extractors: HashMap::new(),
+    }
+}
+
+impl MLayer {
+    /// used by `RateLimitKey::Custom` keys named `name`
+    pub fn key_extractor<F>(mut self, name: &str, extractor: F) -> Self
+    where
+        F: Fn(&Request) -> Option<String> + Send + Sync + 'static,
+    {
+        self.extractors.insert(name.to_owned(), Arc::new(extractor));
+        self
+    }
+}
+
+impl<S> Layer<S> for MLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Middleware {
+            inner,
+            config: self.config.clone(),
+            global: self.global.clone(),
+            rules: self.rules.clone(),
+            extractors: Arc::new(self.extractors.clone()),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    inner: S,
+    config: Arc<ConcurrencyLimitConfig>,
+    global: Option<Arc<ConcurrencyGate>>,
+    rules: Arc<Vec<Rule>>,
+    extractors: Arc<HashMap<String, KeyExtractor>>,
+}
+
+impl<S> Middleware<S> {
+    /// callers sharing a value of every key share a gate, the ip stands for missing values
+    fn key(&self, rule: &Rule, request: &Request) -> String {
+        rule.limiter
+            .keys
+            .iter()
+            .map(|key| {
+                key_dimension(request, key, &self.extractors)
+                    .unwrap_or_else(|| format!("ip:{}", super::extract_ip(request.extensions())))
+            })
+            .collect::<Vec<_>>()
+            .join(":")
+    }
+}
+
+fn service_unavailable(retry_after_sec: u64) -> Response {
+    let mut response =
+        ErrorResponse::new_with_status_code(StatusCode::SERVICE_UNAVAILABLE).into_response();
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, retry_after_sec.into());
+    response
+}
+
+impl<S> Service<Request> for Middleware<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let path = request.uri().path().to_owned();
+        // the global gate is the last one, it is not held while waiting for the others
+        let gates = self
+            .rules
+            .iter()
+            .filter(|rule| rule.matches(&request))
+            .map(|rule| rule.gate(self.key(rule, &request)))
+            .chain(self.global.clone())
+            .collect::<Vec<_>>();
+        let queue_timeout = Duration::from_millis(self.config.queue_timeout_ms);
+        let retry_after_sec = self.config.retry_after_sec;
+        // the ready service must be the one to be called
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            let deadline = Instant::now() + queue_timeout;
+            let mut permits = Vec::with_capacity(gates.len());
+            for gate in gates {
+                match gate.acquire(deadline).await {
+                    Some(permit) => permits.push(permit),
+                    None => {
+                        info!(
+                            monotonic_counter.concurrency_shed_requests = 1_u64,
+                            path = path,
+                            limit = gate.limit(),
+                            "request shed"
+                        );
+                        return Ok(service_unavailable(retry_after_sec));
+                    }
+                }
+            }
+            let response: Response = inner.call(request).await?;
+            let succeeded = !response.status().is_server_error();
+            for permit in permits {
+                permit.complete(succeeded);
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ConcurrencyLimitConfigBuilder, ConcurrencyLimiterBuilder, RateLimitKey};
+    use axum::{body::Body, routing::get, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_concurrency_limit() {
+        let config = ConcurrencyLimitConfigBuilder::default()
+            .max_in_flight(2)
+            .queue_timeout_ms(10)
+            .limiters(vec![ConcurrencyLimiterBuilder::default()
+                .path("/slow".to_owned())
+                .keys(vec![RateLimitKey::Header {
+                    name: "X-Client".to_owned(),
+                }])
+                .max_in_flight(1)
+                .build()
+                .unwrap()])
+            .build()
+            .unwrap();
+        let app = Router::new()
+            .route(
+                "/slow",
+                get(|| tokio::time::sleep(Duration::from_millis(100))),
+            )
+            .route("/fast", get(|| async {}))
+            .layer(new(config));
+        let call = |path: &'static str, client: &'static str| {
+            let app = app.clone();
+            tokio::spawn(async move {
+                let request = Request::get(path)
+                    .header("X-Client", client)
+                    .body(Body::empty())
+                    .unwrap();
+                app.oneshot(request).await.unwrap()
+            })
+        };
+        let slow = call("/slow", "a");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let response = call("/slow", "a").await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[RETRY_AFTER], "1");
+        // other clients have their own gate, up to the global limit
+        let other = call("/slow", "b");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let response = call("/fast", "c").await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(slow.await.unwrap().status(), StatusCode::OK);
+        assert_eq!(other.await.unwrap().status(), StatusCode::OK);
+        let response = call("/fast", "c").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,232 @@
+use crate::http::cookie::{self, CookieConfig};
+use crate::http::header;
+use crate::utils::hash::{signing, verify_signing};
+use crate::utils::http_error_handler::ErrorResponse;
+use crate::utils::random::next_random_alphanumeric;
+use anyhow::anyhow;
+use axum::extract::Request;
+use axum::http::{HeaderMap, Method};
+use axum::response::{IntoResponse, Response};
+use futures_util::future::BoxFuture;
+use ring::digest::{digest, SHA256};
+use serde::Deserialize;
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+use tracing::warn;
+
+const CSRF_TOKEN_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+pub enum CsrfMode {
+    /// a random token is put in a js readable cookie, the client echoes it in `X-CSRF-Token`
+    DoubleSubmit,
+    /// the token is the hmac of the session cookie, nothing but the secret is kept by server
+    Synchronizer,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CsrfConfig {
+    pub mode: CsrfMode,
+    /// the cookie authenticating requests. requests without it are not subject to csrf
+    pub session_cookie: CookieConfig,
+    /// must not be `HttpOnly`, clients read it
+    pub csrf_cookie: CookieConfig,
+    /// required by `Synchronizer`, tokens signed by an empty one can be forged by anyone
+    #[serde(default)]
+    pub secret: String,
+}
+
+impl CsrfConfig {
+    /// token for the session cookie value
+    pub fn new_token(&self, session: &str) -> String {
+        match self.mode {
+            CsrfMode::DoubleSubmit => next_random_alphanumeric(CSRF_TOKEN_LEN),
+            CsrfMode::Synchronizer => signing(session, &self.secret),
+        }
+    }
+
+    fn verify(&self, headers: &HeaderMap, session: &str) -> Result<(), anyhow::Error> {
+        let token = headers
+            .get(header::X_CSRF_TOKEN)
+            .ok_or(anyhow!("missing csrf token"))?
+            .to_str()?;
+        let verified = match self.mode {
+            CsrfMode::DoubleSubmit => cookie::get_cookie(headers, &self.csrf_cookie.name)
+                .is_some_and(|expected| {
+                    !expected.is_empty()
+                        && digest(&SHA256, expected.as_bytes()).as_ref()
+                            == digest(&SHA256, token.as_bytes()).as_ref()
+                }),
+            CsrfMode::Synchronizer => verify_signing(session, &self.secret, token),
+        };
+        if !verified {
+            return Err(anyhow!("csrf token mismatch"));
+        }
+        Ok(())
+    }
+}
+
+/// for login handlers. sets the session cookie and its csrf cookie, returns the csrf token.
+pub fn issue_session_cookies(
+    headers: &mut HeaderMap,
+    config: &CsrfConfig,
+    session: &str,
+) -> Result<String, anyhow::Error> {
+    let token = config.new_token(session);
+    cookie::set_cookie(headers, &config.session_cookie, session)?;
+    cookie::set_cookie(headers, &config.csrf_cookie, &token)?;
+    Ok(token)
+}
+
+/// for logout handlers
+pub fn clear_session_cookies(
+    headers: &mut HeaderMap,
+    config: &CsrfConfig,
+) -> Result<(), anyhow::Error> {
+    cookie::clear_cookie(headers, &config.session_cookie)?;
+    cookie::clear_cookie(headers, &config.csrf_cookie)
+}
+
+/// rejects unsafe requests authenticated by cookie without a valid csrf token
+#[derive(Clone)]
+pub struct MLayer {
+    config: CsrfConfig,
+}
+
+pub fn new(config: CsrfConfig) -> Result<MLayer, anyhow::Error> {
+    if config.mode == CsrfMode::Synchronizer && config.secret.is_empty() {
+        return Err(anyhow!("csrf secret is required by synchronizer mode"));
+    }
+    Ok(MLayer { config })
+}
+
+impl<S> Layer<S> for MLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Middleware {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    inner: S,
+    config: CsrfConfig,
+}
+
+fn is_safe_method(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    )
+}
+
+impl<S> Service<Request> for Middleware<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        if !is_safe_method(request.method()) {
+            if let Some(session) =
+                cookie::get_cookie(request.headers(), &self.config.session_cookie.name)
+            {
+                if let Err(e) = self.config.verify(request.headers(), session) {
+                    warn!(
+                        err = e.to_string(),
+                        path = request.uri().path(),
+                        "csrf verification failed"
+                    );
+                    return Box::pin(async move { Ok(ErrorResponse::new_forb().into_response()) });
+                }
+            }
+        }
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let response: Response = future.await?;
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::cookie::CookieConfigBuilder;
+    use axum::http::StatusCode;
+    use axum::{body::Body, routing::post, Router};
+    use tower::ServiceExt;
+
+    async fn call(config: CsrfConfig, cookie: &str, token: Option<&str>) -> StatusCode {
+        let app = Router::new()
+            .route("/", post(|| async {}))
+            .layer(new(config).unwrap());
+        let mut request = Request::post("/").header("cookie", cookie);
+        if let Some(token) = token {
+            request = request.header(header::X_CSRF_TOKEN, token);
+        }
+        app.oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_csrf() {
+        let mut config = CsrfConfig {
+            mode: CsrfMode::DoubleSubmit,
+            session_cookie: CookieConfigBuilder::default()
+                .name("sid".to_owned())
+                .build()
+                .unwrap(),
+            csrf_cookie: CookieConfigBuilder::default()
+                .name("csrf".to_owned())
+                .http_only(false)
+                .build()
+                .unwrap(),
+            secret: "secret".to_owned(),
+        };
+        let mut headers = HeaderMap::new();
+        let token = issue_session_cookies(&mut headers, &config, "session-key").unwrap();
+        assert_eq!(headers.get_all("set-cookie").iter().count(), 2);
+        let cookie = format!("sid=session-key; csrf={}", token);
+        assert_eq!(
+            call(config.clone(), &cookie, Some(&token)).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            call(config.clone(), &cookie, Some("other")).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            call(config.clone(), &cookie, None).await,
+            StatusCode::FORBIDDEN
+        );
+        // not authenticated by cookie
+        assert_eq!(call(config.clone(), "other=1", None).await, StatusCode::OK);
+
+        config.mode = CsrfMode::Synchronizer;
+        let token = config.new_token("session-key");
+        assert_eq!(
+            call(config.clone(), "sid=session-key", Some(&token)).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            call(config.clone(), "sid=other-key", Some(&token)).await,
+            StatusCode::FORBIDDEN
+        );
+        config.secret = String::new();
+        assert!(new(config).is_err());
+    }
+}
@@ -0,0 +1,291 @@
+use super::client_ip::ClientIpConfig;
+use crate::http::extracts::client_ip::ClientIp;
+use crate::utils::http_error_handler::ErrorResponse;
+use axum::extract::{ConnectInfo, MatchedPath, Request};
+use axum::response::{IntoResponse, Response};
+use derive_builder::Builder;
+use futures_util::future::BoxFuture;
+use ipnet::IpNet;
+use serde::Deserialize;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Default, Deserialize, Builder)]
+pub struct IpFilterConfig {
+    /// used without the `client_ip` middleware in front
+    #[builder(default)]
+    #[serde(default)]
+    pub client_ip: ClientIpConfig,
+    /// every matching rule must let the ip in
+    #[builder(default)]
+    #[serde(default)]
+    pub rules: Vec<IpFilterRule>,
+}
+
+#[derive(Debug, Clone, Deserialize, Builder)]
+pub struct IpFilterRule {
+    /// requests whose path starts with it are filtered, any path if empty
+    #[builder(default)]
+    #[serde(default)]
+    pub path: String,
+    /// axum route template, see `RateLimiter.route`
+    #[builder(default)]
+    #[serde(default)]
+    pub route: Option<String>,
+    /// any ip not denied if empty
+    #[builder(default)]
+    #[serde(default)]
+    pub allow: Vec<IpNet>,
+    /// wins over `allow`
+    #[builder(default)]
+    #[serde(default)]
+    pub deny: Vec<IpNet>,
+}
+
+impl IpFilterRule {
+    fn matches(&self, request: &Request) -> bool {
+        if !request.uri().path().starts_with(&self.path) {
+            return false;
+        }
+        self.route.as_ref().is_none_or(|route| {
+            request
+                .extensions()
+                .get::<MatchedPath>()
+                .is_some_and(|matched| matched.as_str() == route)
+        })
+    }
+
+    /// unknown ips could be denied ones, e.g. without `into_make_service_with_connect_info`
+    fn permits(&self, ip: Option<&IpAddr>) -> bool {
+        let Some(ip) = ip.map(IpAddr::to_canonical) else {
+            return false;
+        };
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+}
+
+/// clones share the config, `reload` applies to every layered service
+#[derive(Clone)]
+pub struct MLayer {
+    config: Arc<RwLock<Arc<IpFilterConfig>>>,
+}
+
+pub fn new(config: IpFilterConfig) -> MLayer {
+    MLayer {
+        config: Arc::new(RwLock::new(Arc::new(config))),
+    }
+}
+
+impl MLayer {
+    /// requests received afterwards are filtered by `config`
+    pub fn reload(&self, config: IpFilterConfig) {
+        match self.config.write() {
+            Ok(mut current) => {
+                info!(rules = config.rules.len(), "ip filter reloaded");
+                *current = Arc::new(config);
+            }
+            Err(_) => warn!("ip filter config poisoned, not reloaded"),
+        }
+    }
+}
+
+impl<S> Layer<S> for MLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Middleware {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    inner: S,
+    config: Arc<RwLock<Arc<IpFilterConfig>>>,
+}
+
+impl<S> Middleware<S> {
+    fn permits(&self, request: &Request) -> bool {
+        let Ok(config) = self.config.read().map(|config| config.clone()) else {
+            warn!("ip filter config poisoned");
+            return false;
+        };
+        let mut rules = config.rules.iter().filter(|rule| rule.matches(request));
+        let ip = request
+            .extensions()
+            .get::<ClientIp>()
+            .map(|ClientIp(ip)| *ip)
+            .or_else(|| {
+                let ConnectInfo(peer) = request.extensions().get::<ConnectInfo<SocketAddr>>()?;
+                Some(config.client_ip.resolve(peer.ip(), request.headers()))
+            });
+        let permitted = rules.all(|rule| rule.permits(ip.as_ref()));
+        if !permitted {
+            info!(
+                ip = ?ip,
+                path = request.uri().path(),
+                "ip filtered"
+            );
+        }
+        permitted
+    }
+}
+
+impl<S> Service<Request> for Middleware<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        if !self.permits(&request) {
+            return Box::pin(async { Ok(ErrorResponse::new_forb().into_response()) });
+        }
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let response: Response = future.await?;
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::header;
+    use crate::http::middlewares::client_ip::ClientIpConfigBuilder;
+    use axum::http::StatusCode;
+    use axum::{body::Body, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn rule(path: &str, allow: &[&str], deny: &[&str]) -> IpFilterRule {
+        let nets = |nets: &[&str]| nets.iter().map(|net| net.parse().unwrap()).collect();
+        IpFilterRuleBuilder::default()
+            .path(path.to_owned())
+            .allow(nets(allow))
+            .deny(nets(deny))
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_ip_filter() {
+        let config = IpFilterConfigBuilder::default()
+            .client_ip(
+                ClientIpConfigBuilder::default()
+                    .trusted_proxies(vec!["10.0.0.0/8".parse().unwrap()])
+                    .build()
+                    .unwrap(),
+            )
+            .rules(vec![
+                rule(
+                    "/admin",
+                    &["192.168.0.0/16", "2001:db8::/32"],
+                    &["192.168.1.0/24"],
+                ),
+                rule("/", &[], &["203.0.113.0/24"]),
+            ])
+            .build()
+            .unwrap();
+        let layer = new(config);
+        let app = Router::new()
+            .route("/admin/users", get(|| async {}))
+            .route("/orders", get(|| async {}))
+            .layer(layer.clone());
+        let call = |path: &'static str, peer: &'static str, forwarded_for: Option<&str>| {
+            let forwarded_for = forwarded_for.map(str::to_owned);
+            let app = app.clone();
+            async move {
+                let mut request = Request::get(path);
+                if let Some(forwarded_for) = forwarded_for {
+                    request = request.header(header::X_FORWARDED_FOR, forwarded_for);
+                }
+                let mut request = request.body(Body::empty()).unwrap();
+                let peer = SocketAddr::new(peer.parse().unwrap(), 443);
+                request.extensions_mut().insert(ConnectInfo(peer));
+                app.oneshot(request).await.unwrap().status()
+            }
+        };
+        assert_eq!(
+            call("/admin/users", "192.168.0.1", None).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            call("/admin/users", "2001:db8::1", None).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            call("/admin/users", "192.168.1.1", None).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            call("/admin/users", "8.8.8.8", None).await,
+            StatusCode::FORBIDDEN
+        );
+        // dual stack listeners see ipv4 peers as mapped ipv6 ones
+        assert_eq!(
+            call("/admin/users", "::ffff:192.168.0.1", None).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            call("/orders", "::ffff:203.0.113.1", None).await,
+            StatusCode::FORBIDDEN
+        );
+        let mapped = "::ffff:192.168.0.1".parse().unwrap();
+        assert!(rule("/", &["192.168.0.0/16"], &[]).permits(Some(&mapped)));
+        assert!(!rule("/", &[], &["192.168.0.0/16"]).permits(Some(&mapped)));
+        assert_eq!(call("/orders", "8.8.8.8", None).await, StatusCode::OK);
+        assert_eq!(
+            call("/orders", "203.0.113.1", None).await,
+            StatusCode::FORBIDDEN
+        );
+        // only trusted proxies can forward
+        assert_eq!(
+            call("/admin/users", "10.0.0.1", Some("192.168.0.1")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            call("/admin/users", "8.8.8.8", Some("192.168.0.1")).await,
+            StatusCode::FORBIDDEN
+        );
+        // trusted proxies only set `X-Forwarded-For`, a `Forwarded` from callers is passed through
+        let mut request = Request::get("/admin/users")
+            .header(header::X_FORWARDED_FOR, "8.8.8.8")
+            .header(axum::http::header::FORWARDED, "for=192.168.0.1")
+            .body(Body::empty())
+            .unwrap();
+        let peer = SocketAddr::new("10.0.0.1".parse().unwrap(), 443);
+        request.extensions_mut().insert(ConnectInfo(peer));
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        // unknown ips could be denied ones
+        let request = Request::get("/orders").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        layer.reload(IpFilterConfig {
+            rules: vec![rule("/admin", &["8.8.8.0/24"], &[])],
+            ..Default::default()
+        });
+        assert_eq!(call("/admin/users", "8.8.8.8", None).await, StatusCode::OK);
+        assert_eq!(
+            call("/admin/users", "192.168.0.1", None).await,
+            StatusCode::FORBIDDEN
+        );
+    }
+}
@@ -1,28 +1,323 @@
-use crate::http::user_token::TokenUser;
-use crate::utils::http_error_handler::ErrorResponse;
-use axum::extract::Request;
-use axum::response::IntoResponse;
-use axum::Extension;
-use axum::{
-    http::{header, StatusCode},
-    response::Response,
+use crate::auth::impersonation;
+use crate::http::authenticator::{
+    self, BearerError, Challenge, AUTH_METHOD_KEY_JWT, DEFAULT_REALM,
 };
+use crate::http::cookie;
+use crate::http::user_token::{Actor, TokenUser};
+use axum::extract::Request;
+use axum::http::{header, Uri};
+use axum::response::{IntoResponse, Response};
 use derive_builder::Builder;
 use futures_util::future::BoxFuture;
-use jsonwebtoken::{decode, DecodingKey, Validation};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt::{Display, Formatter};
 use std::task::{Context, Poll};
 use tower::{Layer, Service};
-use tracing::error;
+use tracing::info;
 
-const AUTH_METHOD_KEY_JWT: &str = "Bearer";
+const JWT_DEFAULT_LEEWAY_SEC: u64 = 60;
 
-#[derive(Clone)]
+#[derive(Clone, Deserialize, Builder)]
 pub struct JwtAuthConfig {
-    issuer: String,
-    secret: String,
+    /// tokens issued by this service carry it, always accepted
+    pub issuer: String,
+    pub secret: String,
+    /// other accepted issuers
+    #[builder(default)]
+    #[serde(default)]
+    pub accepted_issuers: Vec<String>,
+    /// `aud` is not checked if empty
+    #[builder(default)]
+    #[serde(default)]
+    pub audiences: Vec<String>,
+    /// tolerance applied to `exp`, `nbf` and `iat`
+    #[builder(default = "JWT_DEFAULT_LEEWAY_SEC")]
+    #[serde(default = "default_leeway_sec")]
+    pub leeway_sec: u64,
+    /// claims which must be present besides `exp`, `iss` and `iat`
+    #[builder(default)]
+    #[serde(default)]
+    pub required_claims: Vec<String>,
+    /// tokens issued longer ago are rejected, whatever their `exp` is
+    #[builder(default)]
+    #[serde(default)]
+    pub max_age_sec: Option<u64>,
+    /// realm of `WWW-Authenticate` challenges
+    #[builder(default = "DEFAULT_REALM.to_owned()")]
+    #[serde(default = "default_realm")]
+    pub realm: String,
+    /// where the middleware looks for tokens, tried in order
+    #[builder(default = "default_token_sources()")]
+    #[serde(default = "default_token_sources")]
+    pub token_sources: Vec<TokenSource>,
+}
+
+fn default_leeway_sec() -> u64 {
+    JWT_DEFAULT_LEEWAY_SEC
+}
+
+fn default_realm() -> String {
+    DEFAULT_REALM.to_owned()
+}
+
+fn default_token_sources() -> Vec<TokenSource> {
+    vec![TokenSource::Authorization {
+        scheme: default_scheme(),
+    }]
+}
+
+fn default_scheme() -> String {
+    AUTH_METHOD_KEY_JWT.to_owned()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TokenSource {
+    Authorization {
+        #[serde(default = "default_scheme")]
+        scheme: String,
+    },
+    Header {
+        name: String,
+    },
+    Cookie {
+        name: String,
+    },
+    /// for clients unable to send headers, e.g. websockets and download links.
+    /// only accepted on `paths` and the paths below them, but stripped from every uri.
+    Query {
+        name: String,
+        paths: Vec<String>,
+    },
+}
+
+impl TokenSource {
+    fn extract(&self, request: &Request) -> Option<String> {
+        let headers = request.headers();
+        match self {
+            TokenSource::Authorization { scheme } => authenticator::extract_authorization(headers)
+                .filter(|(s, _)| s == scheme)
+                .map(|(_, token)| token.to_owned()),
+            TokenSource::Header { name } => headers
+                .get(name.as_str())
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned),
+            TokenSource::Cookie { name } => cookie::get_cookie(headers, name).map(str::to_owned),
+            TokenSource::Query { name, paths } => {
+                let path = request.uri().path();
+                if !paths.iter().any(|p| is_below(path, p)) {
+                    return None;
+                }
+                serde_urlencoded::from_str::<Vec<(String, String)>>(request.uri().query()?)
+                    .ok()?
+                    .into_iter()
+                    .find_map(|(k, v)| (&k == name).then_some(v))
+            }
+        }
+        .filter(|token| !token.is_empty())
+    }
+
+    /// tokens must not reach upstream handlers nor access logs
+    fn strip(&self, request: &mut Request) {
+        match self {
+            // instead of x-token-user header
+            TokenSource::Authorization { .. } => {
+                request.headers_mut().remove(header::AUTHORIZATION);
+            }
+            TokenSource::Header { name } => {
+                request.headers_mut().remove(name.as_str());
+            }
+            TokenSource::Cookie { .. } => {}
+            TokenSource::Query { name, .. } => {
+                if let Some(uri) = strip_query_param(request.uri(), name) {
+                    *request.uri_mut() = uri;
+                }
+            }
+        }
+    }
+}
+
+/// `path` is `parent` or one of its sub paths, `/ws` covers `/ws/chat` but not `/wss`
+fn is_below(path: &str, parent: &str) -> bool {
+    path.strip_prefix(parent)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/') || parent.ends_with('/'))
+}
+
+/// removes the `name` params only, other params are kept as sent
+fn strip_query_param(uri: &Uri, name: &str) -> Option<Uri> {
+    let query = uri.query()?;
+    let is_named = |param: &&str| {
+        let key = param.split_once('=').map_or(*param, |(key, _)| key);
+        serde_urlencoded::from_str::<Vec<(String, String)>>(key)
+            .is_ok_and(|pairs| pairs.first().is_some_and(|(key, _)| key == name))
+    };
+    if !query.split('&').any(|param| is_named(&param)) {
+        return None;
+    }
+    let query = query
+        .split('&')
+        .filter(|param| !is_named(param))
+        .collect::<Vec<_>>()
+        .join("&");
+    let path_and_query = if query.is_empty() {
+        uri.path().to_owned()
+    } else {
+        format!("{}?{}", uri.path(), query)
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse().ok()?);
+    Uri::from_parts(parts).ok()
+}
+
+/// why a token is rejected
+#[derive(Debug)]
+pub enum JwtValidationError {
+    Malformed(String),
+    InvalidSignature,
+    Expired,
+    NotYetValid,
+    InvalidIssuer,
+    InvalidAudience,
+    MissingClaim(String),
+    TooOld,
+}
+
+impl JwtValidationError {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::Malformed(_) => "malformed",
+            Self::InvalidSignature => "invalid_signature",
+            Self::Expired => "expired",
+            Self::NotYetValid => "not_yet_valid",
+            Self::InvalidIssuer => "invalid_issuer",
+            Self::InvalidAudience => "invalid_audience",
+            Self::MissingClaim(_) => "missing_claim",
+            Self::TooOld => "too_old",
+        }
+    }
+}
+
+impl Display for JwtValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Malformed(e) => write!(f, "malformed token: {}", e),
+            Self::MissingClaim(claim) => write!(f, "missing required claim {}", claim),
+            _ => write!(f, "{}", self.reason()),
+        }
+    }
+}
+
+impl std::error::Error for JwtValidationError {}
+
+impl From<jsonwebtoken::errors::Error> for JwtValidationError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        match e.kind() {
+            ErrorKind::InvalidSignature => Self::InvalidSignature,
+            ErrorKind::ExpiredSignature => Self::Expired,
+            ErrorKind::ImmatureSignature => Self::NotYetValid,
+            ErrorKind::InvalidIssuer => Self::InvalidIssuer,
+            ErrorKind::InvalidAudience => Self::InvalidAudience,
+            ErrorKind::MissingRequiredClaim(claim) => Self::MissingClaim(claim.clone()),
+            _ => Self::Malformed(e.to_string()),
+        }
+    }
 }
 
+impl JwtAuthConfig {
+    fn validation(&self) -> Validation {
+        let mut validation = Validation::new(jsonwebtoken::Algorithm::HS512);
+        let mut issuers = vec![self.issuer.as_str()];
+        issuers.extend(self.accepted_issuers.iter().map(String::as_str));
+        validation.set_issuer(&issuers);
+        if self.audiences.is_empty() {
+            validation.validate_aud = false;
+            validation.set_required_spec_claims(&["exp", "iss"]);
+        } else {
+            validation.set_audience(&self.audiences);
+            validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+        }
+        validation.leeway = self.leeway_sec;
+        validation.validate_nbf = true;
+        validation
+    }
+
+    pub(crate) fn validate(&self, token: &str) -> Result<JwtClaims, JwtValidationError> {
+        let claims = decode::<Map<String, Value>>(
+            token,
+            &DecodingKey::from_secret(self.secret.as_bytes()),
+            &self.validation(),
+        )?
+        .claims;
+        if let Some(claim) = self
+            .required_claims
+            .iter()
+            .find(|claim| !claims.contains_key(claim.as_str()))
+        {
+            return Err(JwtValidationError::MissingClaim(claim.clone()));
+        }
+        let claims: JwtClaims = serde_json::from_value(Value::Object(claims))
+            .map_err(|e| JwtValidationError::Malformed(e.to_string()))?;
+        if let Some(max_age_sec) = self.max_age_sec {
+            let now = chrono::Utc::now().timestamp() as u64;
+            if (claims.iat as u64) + max_age_sec + self.leeway_sec < now {
+                return Err(JwtValidationError::TooOld);
+            }
+        }
+        Ok(claims)
+    }
+
+    /// claims are completed with `iss`, `aud`, `iat` and `exp` of this config,
+    /// `tid` and `act` default to the ones of `cla`
+    pub(crate) fn issue_token(
+        &self,
+        claims: &mut JwtClaimsBuilder,
+        ttl_sec: u64,
+    ) -> Result<String, anyhow::Error> {
+        let now = chrono::Utc::now().timestamp() as usize;
+        let aud = (!self.audiences.is_empty()).then(|| Audience::Many(self.audiences.clone()));
+        if claims.tid.is_none() {
+            let tid = claims.cla.as_ref().and_then(|cla| cla.tenant_id.clone());
+            claims.tid(tid);
+        }
+        if claims.act.is_none() {
+            let act = claims.cla.as_ref().and_then(|cla| cla.act.clone());
+            claims.act(act);
+        }
+        let claims = claims
+            .iss(self.issuer.clone())
+            .aud(aud)
+            .iat(now)
+            .exp(now + ttl_sec as usize)
+            .build()?;
+        Ok(encode(
+            &Header::new(jsonwebtoken::Algorithm::HS512),
+            &claims,
+            &EncodingKey::from_secret(self.secret.as_bytes()),
+        )?)
+    }
+
+    pub fn parse_token_user(&self, token: &str) -> Result<TokenUser, JwtValidationError> {
+        self.validate(token)
+            .map(|claims| TokenUser {
+                tenant_id: claims.tid.or(claims.cla.tenant_id),
+                act: claims.act.or(claims.cla.act),
+                ..claims.cla
+            })
+            .inspect_err(|e| {
+                info!(
+                    monotonic_counter.jwt_validation_failures = 1_u64,
+                    reason = e.reason(),
+                    "jwt validation failed"
+                );
+            })
+    }
+}
+
+/// tokens are stripped before calling inner services, trace layers inside this one never log them.
+/// trace layers outside of it see query tokens, they must log `MLayer::redact_uri` instead
 #[derive(Clone)]
 pub struct MLayer {
     config: JwtAuthConfig,
@@ -32,6 +327,19 @@ pub fn new(config: JwtAuthConfig) -> MLayer {
     MLayer { config }
 }
 
+impl MLayer {
+    /// `uri` without query tokens, e.g. for `TraceLayer::make_span_with`
+    pub fn redact_uri(&self, uri: &Uri) -> Uri {
+        self.config
+            .token_sources
+            .iter()
+            .fold(uri.clone(), |uri, source| match source {
+                TokenSource::Query { name, .. } => strip_query_param(&uri, name).unwrap_or(uri),
+                _ => uri,
+            })
+    }
+}
+
 // TODO too many copy. may need refractor
 impl<S> Layer<S> for MLayer {
     type Service = Middleware<S>;
@@ -50,12 +358,38 @@ pub struct Middleware<S> {
     config: JwtAuthConfig,
 }
 
+/// `aud` may be a string or an array
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
 #[derive(Debug, Serialize, Deserialize, Builder)]
 pub(crate) struct JwtClaims {
-    exp: usize,
-    iss: String,
-    iat: usize,
-    cla: TokenUser,
+    pub(crate) exp: usize,
+    pub(crate) iss: String,
+    pub(crate) iat: usize,
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) nbf: Option<usize>,
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) aud: Option<Audience>,
+    /// the oauth client a token was issued to
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) client_id: Option<String>,
+    /// tenant of `cla`, mirrored into `TokenUser::tenant_id` on validation
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) tid: Option<String>,
+    /// the real actor of impersonation tokens
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) act: Option<Actor>,
+    pub(crate) cla: TokenUser,
 }
 
 impl<S> Service<Request> for Middleware<S>
@@ -72,62 +406,222 @@ where
     }
 
     fn call(&mut self, mut request: Request) -> Self::Future {
-        let mut proceed = true;
-        if let Some((auth_header, token)) = self.extract_authentication_fields(&request) {
-            match auth_header {
-                AUTH_METHOD_KEY_JWT => match self.parse_token_user(token) {
-                    Ok(token_user) => {
-                        let _ = request.extensions_mut().insert(Extension(token_user));
-                    }
-                    Err(e) => {
-                        error!(
-                            token = token,
-                            err = e.to_string(),
-                            "parse authentication header error"
-                        );
-                        proceed = false;
+        let mut rejected = None;
+        let mut impersonated = None;
+        let token = self
+            .config
+            .token_sources
+            .iter()
+            .find_map(|source| source.extract(&request));
+        for source in self.config.token_sources.iter() {
+            source.strip(&mut request);
+        }
+        if let Some(token) = token {
+            match self.config.parse_token_user(&token) {
+                Ok(token_user) => {
+                    if token_user.is_impersonated() {
+                        impersonated = Some((
+                            token_user.clone(),
+                            super::extract_ip(request.extensions()),
+                            request.method().clone(),
+                            request.uri().path().to_owned(),
+                        ));
                     }
-                },
-                _ => {}
+                    let _ = request.extensions_mut().insert(token_user);
+                }
+                // logged with its reason by `parse_token_user`
+                Err(e) => {
+                    rejected = Some(
+                        Challenge::bearer(&self.config.realm)
+                            .error(BearerError::InvalidToken, &e.to_string()),
+                    );
+                }
             }
-            // instead of x-token-user header
-            request.headers_mut().remove(header::AUTHORIZATION);
         }
-        if proceed {
-            let future = self.inner.call(request);
-            return Box::pin(async move {
-                let response: Response = future.await?;
-                Ok(response)
-            });
+        if let Some(challenge) = rejected {
+            return Box::pin(async move { Ok(challenge.into_response()) });
         }
+        let future = self.inner.call(request);
         Box::pin(async move {
-            Ok(ErrorResponse::new_with_status_code(StatusCode::UNAUTHORIZED).into_response())
+            let response: Response = future.await?;
+            if let Some((token_user, ip, method, path)) = impersonated {
+                impersonation::audit(&token_user, &ip, &method, &path, response.status());
+            }
+            Ok(response)
         })
     }
 }
 
-impl<S> Middleware<S> {
-    fn extract_authentication_fields<'a>(&self, req: &'a Request) -> Option<(&'a str, &'a str)> {
-        if let Some(val) = req.headers().get(header::AUTHORIZATION) {
-            if let Ok(val) = val.to_str() {
-                let splits: Vec<&str> = val.split(" ").collect();
-                if splits.len() == 2 {
-                    return Some((splits[0], splits[1]));
-                }
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(builder: &mut JwtClaimsBuilder) -> String {
+        let now = chrono::Utc::now().timestamp() as usize;
+        if builder.iat.is_none() {
+            builder.iat(now);
         }
-        return None;
+        let claims = builder
+            .iss("issuer".to_owned())
+            .exp(now + 600)
+            .cla(TokenUser::default())
+            .build()
+            .unwrap();
+        encode(
+            &Header::new(jsonwebtoken::Algorithm::HS512),
+            &claims,
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap()
     }
 
-    fn parse_token_user(&self, token: &str) -> Result<TokenUser, anyhow::Error> {
-        let mut validator = Validation::new(jsonwebtoken::Algorithm::HS512);
-        validator.set_issuer(&vec![&self.config.issuer]);
-        Ok(decode::<JwtClaims>(
-            token,
-            &DecodingKey::from_secret(self.config.secret.as_bytes()),
-            &validator,
-        )?
-        .claims
-        .cla)
+    fn reason(config: &JwtAuthConfig, token: &str) -> &'static str {
+        config.parse_token_user(token).unwrap_err().reason()
+    }
+
+    #[test]
+    fn test_jwt_validation_options() {
+        let config = JwtAuthConfigBuilder::default()
+            .issuer("issuer".to_owned())
+            .secret("secret".to_owned())
+            .audiences(vec!["api".to_owned()])
+            .leeway_sec(0)
+            .max_age_sec(Some(300))
+            .build()
+            .unwrap();
+        let now = chrono::Utc::now().timestamp() as usize;
+        let aud = Some(Audience::One("api".to_owned()));
+        assert!(config
+            .parse_token_user(&token(JwtClaimsBuilder::default().aud(aud.clone())))
+            .is_ok());
+        assert_eq!(
+            reason(&config, &token(&mut JwtClaimsBuilder::default())),
+            "missing_claim"
+        );
+        assert_eq!(
+            reason(
+                &config,
+                &token(
+                    JwtClaimsBuilder::default().aud(Some(Audience::Many(vec!["other".to_owned()])))
+                )
+            ),
+            "invalid_audience"
+        );
+        assert_eq!(
+            reason(
+                &config,
+                &token(
+                    JwtClaimsBuilder::default()
+                        .aud(aud.clone())
+                        .nbf(Some(now + 60))
+                )
+            ),
+            "not_yet_valid"
+        );
+        assert_eq!(
+            reason(
+                &config,
+                &token(JwtClaimsBuilder::default().aud(aud).iat(now - 600))
+            ),
+            "too_old"
+        );
+        assert_eq!(reason(&config, "not.a.token"), "malformed");
+    }
+
+    #[tokio::test]
+    async fn test_token_sources() {
+        use axum::http::StatusCode;
+        use axum::{body::Body, routing::get, Extension, Router};
+        use tower::ServiceExt;
+
+        let config = JwtAuthConfigBuilder::default()
+            .issuer("issuer".to_owned())
+            .secret("secret".to_owned())
+            .token_sources(vec![
+                TokenSource::Cookie {
+                    name: "token".to_owned(),
+                },
+                TokenSource::Query {
+                    name: "access_token".to_owned(),
+                    paths: vec!["/ws".to_owned()],
+                },
+            ])
+            .build()
+            .unwrap();
+        let token = token(&mut JwtClaimsBuilder::default());
+        let handler = |user: Option<Extension<TokenUser>>, uri: Uri| async move {
+            format!("{} {}", user.is_some(), uri)
+        };
+        let app = Router::new()
+            .route("/ws", get(handler))
+            .route("/ws/chat", get(handler))
+            .route("/wss", get(handler))
+            .route("/download", get(handler))
+            .layer(new(config));
+        let call = |request: Request| async {
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+
+        let uri = format!("/ws?access_token={}&room=1", token);
+        let body = call(Request::get(uri).body(Body::empty()).unwrap()).await;
+        assert_eq!(body, "true /ws?room=1");
+        // other params are passed as sent
+        let uri = format!("/ws/chat?room=a%20b&access_token={}&q=+", token);
+        let body = call(Request::get(uri).body(Body::empty()).unwrap()).await;
+        assert_eq!(body, "true /ws/chat?room=a%20b&q=+");
+        let uri = format!("/wss?access_token={}", token);
+        let body = call(Request::get(uri).body(Body::empty()).unwrap()).await;
+        assert_eq!(body, "false /wss");
+        let uri = format!("/download?access_token={}", token);
+        let body = call(Request::get(uri).body(Body::empty()).unwrap()).await;
+        assert_eq!(body, "false /download");
+        let request = Request::get("/download")
+            .header(header::COOKIE, format!("token={}", token))
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(call(request).await, "true /download");
+    }
+
+    #[tokio::test]
+    async fn test_redact_uri() {
+        use axum::{body::Body, routing::get, Router};
+        use std::sync::{Arc, Mutex};
+        use tower::ServiceExt;
+        use tower_http::trace::TraceLayer;
+
+        let config = JwtAuthConfigBuilder::default()
+            .issuer("issuer".to_owned())
+            .secret("secret".to_owned())
+            .token_sources(vec![TokenSource::Query {
+                name: "access_token".to_owned(),
+                paths: vec!["/ws".to_owned()],
+            }])
+            .build()
+            .unwrap();
+        let layer = new(config);
+        let logged = Arc::new(Mutex::new(Vec::new()));
+        let app = Router::new()
+            .route("/ws", get(|| async {}))
+            .layer(layer.clone())
+            .layer(TraceLayer::new_for_http().make_span_with({
+                let logged = logged.clone();
+                move |request: &Request| {
+                    let uri = layer.redact_uri(request.uri());
+                    logged.lock().unwrap().push(uri.to_string());
+                    tracing::info_span!("request", uri = %uri)
+                }
+            }));
+        let uri = format!(
+            "/ws?access_token={}&room=1",
+            token(&mut JwtClaimsBuilder::default())
+        );
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        app.oneshot(request).await.unwrap();
+        assert_eq!(*logged.lock().unwrap(), ["/ws?room=1"]);
     }
 }
@@ -1,5 +1,33 @@
-mod jwt_authentication;
-mod request_id;
+//! metrics are events with `monotonic_counter.` fields, exported as counters by metrics subscribers
 
-#[cfg(feature = "redis")]
-mod redis_rate_limiter;
+pub mod authentication;
+pub mod client_ip;
+pub mod concurrency_limiter;
+pub mod csrf;
+pub mod ip_filter;
+pub mod jwt_authentication;
+pub mod open_api_authentication;
+pub mod rate_limiter;
+pub mod request_id;
+pub mod require_scope;
+pub mod tenant;
+pub mod token_user_forward;
+pub mod token_user_trust;
+
+/// `ClientIp` resolved by the `client_ip` middleware, then the peer address.
+/// forwarding headers are never read here, they can be forged by callers.
+pub(crate) fn extract_ip(extensions: &axum::http::Extensions) -> String {
+    use crate::http::extracts::client_ip::ClientIp;
+    use axum::extract::ConnectInfo;
+    use std::net::SocketAddr;
+
+    extensions
+        .get::<ClientIp>()
+        .map(|ClientIp(ip)| ip.to_string())
+        .or_else(|| {
+            extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip().to_string())
+        })
+        .unwrap_or("UNKNOWN-IP".to_owned())
+}
@@ -0,0 +1,282 @@
+use crate::auth::open_api::{self, OpenPartnerStore, OpenToken};
+use crate::http::header;
+use crate::utils::http_error_handler::ErrorResponse;
+use crate::utils::nonce::NonceStore;
+use anyhow::anyhow;
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use derive_builder::Builder;
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+use tracing::{debug, error};
+
+const OPEN_API_NONCE_KEY_PREFIX: &str = "open_api:nonce:";
+
+#[derive(Clone, Deserialize, Builder)]
+pub struct OpenApiAuthConfig {
+    /// allowed distance between the signed timestamp and now
+    #[builder(default = "300")]
+    pub max_clock_skew_sec: i64,
+    /// bodies are buffered for hashing
+    #[builder(default = "2 * 1024 * 1024")]
+    pub max_body_bytes: usize,
+}
+
+/// authenticate requests carrying `X-Use-Open-Token` by the `X-Open-Token` signature.
+/// other requests are passed through.
+#[derive(Clone)]
+pub struct MLayer {
+    config: OpenApiAuthConfig,
+    partners: Arc<dyn OpenPartnerStore>,
+    nonces: Arc<dyn NonceStore>,
+}
+
+pub fn new(
+    config: OpenApiAuthConfig,
+    partners: Arc<dyn OpenPartnerStore>,
+    nonces: Arc<dyn NonceStore>,
+) -> MLayer {
+    MLayer {
+        config,
+        partners,
+        nonces,
+    }
+}
+
+impl<S> Layer<S> for MLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Middleware {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    inner: S,
+    layer: MLayer,
+}
+
+fn use_open_token(parts: &Parts) -> bool {
+    parts
+        .headers
+        .get(header::X_USE_OPEN_TOKEN)
+        .and_then(|val| val.to_str().ok())
+        .is_some_and(|val| val == "1" || val.eq_ignore_ascii_case("true"))
+}
+
+impl MLayer {
+    async fn authenticate(&self, parts: &mut Parts, body: &[u8]) -> Result<(), anyhow::Error> {
+        let token = parts
+            .headers
+            .get(header::X_OPEN_TOKEN)
+            .ok_or(anyhow!("missing open token"))?
+            .to_str()?;
+        let token = OpenToken::parse(token)?;
+        let now = chrono::Utc::now().timestamp();
+        if (now - token.timestamp).abs() > self.config.max_clock_skew_sec {
+            return Err(anyhow!("open token timestamp out of range"));
+        }
+        let partner = self
+            .partners
+            .find(&token.app_id)
+            .await?
+            .ok_or(anyhow!("unknown partner {}", token.app_id))?;
+        if !open_api::verify_request(
+            &token,
+            &partner.secret,
+            parts.method.as_str(),
+            parts.uri.path(),
+            parts.uri.query(),
+            body,
+        ) {
+            return Err(anyhow!("invalid open token signature"));
+        }
+        // checked after the signature so that nonces can't be burnt by forged requests
+        let nonce_key = format!(
+            "{}{}:{}",
+            OPEN_API_NONCE_KEY_PREFIX, token.app_id, token.nonce
+        );
+        if !self
+            .nonces
+            .check_and_set(&nonce_key, 2 * self.config.max_clock_skew_sec)
+            .await?
+        {
+            return Err(anyhow!("open token nonce replayed"));
+        }
+        debug!(app_id = token.app_id, "open api partner authenticated");
+        parts.extensions.insert(partner.user);
+        Ok(())
+    }
+}
+
+impl<S> Service<Request> for Middleware<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let layer = self.layer.clone();
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            let (mut parts, body) = request.into_parts();
+            if !use_open_token(&parts) {
+                return inner.call(Request::from_parts(parts, body)).await;
+            }
+            let body = match axum::body::to_bytes(body, layer.config.max_body_bytes).await {
+                Ok(body) => body,
+                Err(e) => {
+                    error!(err = e.to_string(), "read open api body error");
+                    if is_length_limit_error(&e) {
+                        return Ok(ErrorResponse::new_with_status_code(
+                            StatusCode::PAYLOAD_TOO_LARGE,
+                        )
+                        .into_response());
+                    }
+                    return Ok(ErrorResponse::new_no_auth().into_response());
+                }
+            };
+            if let Err(e) = layer.authenticate(&mut parts, &body).await {
+                error!(err = e.to_string(), "open api authentication error");
+                return Ok(ErrorResponse::new_no_auth().into_response());
+            }
+            parts.headers.remove(header::X_OPEN_TOKEN);
+            parts.headers.remove(header::X_USE_OPEN_TOKEN);
+            inner
+                .call(Request::from_parts(parts, Body::from(body)))
+                .await
+        })
+    }
+}
+
+/// bodies over `max_body_bytes` are no authentication failure
+fn is_length_limit_error(e: &axum::Error) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(e);
+    while let Some(e) = source {
+        if e.is::<http_body_util::LengthLimitError>() {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::open_api::OpenPartner;
+    use crate::http::user_token::TokenUser;
+    use crate::utils::nonce::InMemoryNonceStore;
+    use axum::{routing::post, Extension, Router};
+    use std::collections::HashMap;
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        app_with(OpenApiAuthConfigBuilder::default().build().unwrap())
+    }
+
+    fn app_with(config: OpenApiAuthConfig) -> Router {
+        let partners = HashMap::from([(
+            "app".to_owned(),
+            OpenPartner {
+                app_id: "app".to_owned(),
+                secret: "secret".to_owned(),
+                user: TokenUser {
+                    user_id: 3,
+                    ..Default::default()
+                },
+            },
+        )]);
+        Router::new()
+            .route(
+                "/orders",
+                post(
+                    |Extension(user): Extension<TokenUser>| async move { user.user_id.to_string() },
+                ),
+            )
+            .layer(new(
+                config,
+                Arc::new(partners),
+                Arc::new(InMemoryNonceStore::new()),
+            ))
+    }
+
+    fn request(token: &OpenToken, body: &'static str) -> Request {
+        Request::post("/orders?b=2&a=1")
+            .header(header::X_USE_OPEN_TOKEN, "true")
+            .header(header::X_OPEN_TOKEN, token.to_header_value())
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_open_api_signature() {
+        let app = app();
+        let token = open_api::sign_request(
+            "app",
+            "secret",
+            "POST",
+            "/orders",
+            Some("a=1&b=2"),
+            b"{}",
+            "nonce-1",
+        );
+        let response = app.clone().oneshot(request(&token, "{}")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        // replayed
+        let response = app.clone().oneshot(request(&token, "{}")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        // body tampered
+        let token = open_api::sign_request(
+            "app",
+            "secret",
+            "POST",
+            "/orders",
+            Some("a=1&b=2"),
+            b"{}",
+            "nonce-2",
+        );
+        let response = app.oneshot(request(&token, "{\"a\":1}")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_body_too_large() {
+        let app = app_with(
+            OpenApiAuthConfigBuilder::default()
+                .max_body_bytes(4)
+                .build()
+                .unwrap(),
+        );
+        let body = "{\"a\":1}";
+        let token = open_api::sign_request(
+            "app",
+            "secret",
+            "POST",
+            "/orders",
+            Some("a=1&b=2"),
+            body.as_bytes(),
+            "nonce-1",
+        );
+        let response = app.oneshot(request(&token, body)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -0,0 +1,824 @@
+use crate::config::{
+    MemoryStoreConfig, RateLimitConfig, RateLimitFailurePolicy, RateLimitKey, RateLimiter,
+};
+use crate::http::authenticator::ApiKeyFingerprint;
+use crate::http::header;
+use crate::http::user_token::TokenUser;
+use crate::rate_limit::{
+    CircuitBreaker, InMemoryRateLimitStore, RateLimit, RateLimitBackend, RateLimitDecision,
+    RateLimitRejection, RateLimitStore,
+};
+use crate::utils::hash::SignedContent;
+use crate::utils::http_error_handler::ErrorResponse;
+use crate::utils::nonce::NonceStore;
+use axum::extract::{MatchedPath, Request};
+use axum::http::{header::RETRY_AFTER, HeaderMap, Method, StatusCode};
+use axum::response::{IntoResponse, Response};
+use futures_util::future::BoxFuture;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tower::{Layer, Service};
+use tracing::{debug, error, info, warn};
+
+const RATE_LIMITER_KEY_BASE_PREFIX: &str = "gateway:rate_limiter:";
+const FORWARD_KEY_NONCE_PREFIX: &str = "rate_limiter:forward:";
+
+/// custom key dimension, `None` falls back to the ip
+pub type KeyExtractor = Arc<dyn Fn(&Request) -> Option<String> + Send + Sync>;
+
+/// a `RateLimiter` ready to match requests
+struct Rule {
+    limiter: RateLimiter,
+    id: String,
+    methods: Vec<Method>,
+}
+
+impl Rule {
+    fn new(limiter: &RateLimiter) -> Self {
+        let methods = limiter
+            .methods
+            .iter()
+            .filter_map(|method| {
+                Method::from_bytes(method.to_uppercase().as_bytes())
+                    .inspect_err(|_| warn!(method = method, "invalid rate limit method"))
+                    .ok()
+            })
+            .collect();
+        Self {
+            limiter: limiter.clone(),
+            id: limiter.id(),
+            methods,
+        }
+    }
+
+    /// every given condition must hold
+    fn matches(&self, request: &Request) -> bool {
+        let limiter = &self.limiter;
+        let path = request.uri().path();
+        if !self.methods.is_empty() && !self.methods.contains(request.method()) {
+            return false;
+        }
+        if !path.starts_with(&limiter.path) || (limiter.strict && path != limiter.path) {
+            return false;
+        }
+        if let Some(route) = &limiter.route {
+            let matched = request.extensions().get::<MatchedPath>();
+            if matched.is_none_or(|matched| matched.as_str() != route) {
+                return false;
+            }
+        }
+        limiter
+            .pattern
+            .as_ref()
+            .is_none_or(|pattern| pattern.0.is_match(path))
+    }
+}
+
+#[derive(Clone)]
+pub struct MLayer {
+    backend: RateLimitBackend,
+    breaker: Arc<CircuitBreaker>,
+    fallback: Arc<InMemoryRateLimitStore>,
+    config: Arc<RateLimitConfig>,
+    rules: Arc<Vec<Rule>>,
+    extractors: HashMap<String, KeyExtractor>,
+}
+
+/// see `RateLimitBackend::from_config` for the backend selected by config
+pub fn new(config: RateLimitConfig, backend: RateLimitBackend) -> MLayer {
+    MLayer {
+        backend,
+        breaker: Arc::new(CircuitBreaker::new(&config.circuit_breaker)),
+        fallback: Arc::new(InMemoryRateLimitStore::new(&MemoryStoreConfig::default())),
+        rules: Arc::new(config.limiters.iter().map(Rule::new).collect()),
+        config: Arc::new(config),
+        extractors: HashMap::new(),
+    }
+}
+
+impl MLayer {
+    /// see `issue_forward_key`
+    pub fn issue_forward_key(&self, path: &str) -> Result<String, anyhow::Error> {
+        issue_forward_key(
+            path,
+            &self.config.forward_key_secret,
+            SignedContent::<String>::DEFAULT_EXPIRATION,
+        )
+    }
+
+    /// used by `RateLimitKey::Custom` rules named `name`
+    pub fn key_extractor<F>(mut self, name: &str, extractor: F) -> Self
+    where
+        F: Fn(&Request) -> Option<String> + Send + Sync + 'static,
+    {
+        self.extractors.insert(name.to_owned(), Arc::new(extractor));
+        self
+    }
+}
+
+impl<S> Layer<S> for MLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Middleware {
+            inner,
+            backend: self.backend.clone(),
+            breaker: self.breaker.clone(),
+            fallback: self.fallback.clone(),
+            config: self.config.clone(),
+            rules: self.rules.clone(),
+            extractors: Arc::new(self.extractors.clone()),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    inner: S,
+    config: Arc<RateLimitConfig>,
+    backend: RateLimitBackend,
+    /// guards `backend.limits`
+    breaker: Arc<CircuitBreaker>,
+    /// enforces `RateLimitFailurePolicy::Local` rules while the store is unavailable
+    fallback: Arc<InMemoryRateLimitStore>,
+    rules: Arc<Vec<Rule>>,
+    extractors: Arc<HashMap<String, KeyExtractor>>,
+}
+
+/// the tenant of the authenticated user. `Tenant` of anonymous callers comes from
+/// headers or the host they choose, a new one for each request would escape quotas.
+fn authenticated_tenant(request: &Request) -> Option<&str> {
+    request
+        .extensions()
+        .get::<TokenUser>()
+        .and_then(|user| user.tenant_id.as_deref())
+}
+
+/// `None` if the request has no value of `key`
+pub(crate) fn key_dimension(
+    request: &Request,
+    key: &RateLimitKey,
+    extractors: &HashMap<String, KeyExtractor>,
+) -> Option<String> {
+    match key {
+        RateLimitKey::Ip => None,
+        RateLimitKey::User => request
+            .extensions()
+            .get::<TokenUser>()
+            .map(|user| format!("user:{}", user.user_id)),
+        // only verified keys, callers could send a new unverified one for each request
+        RateLimitKey::ApiKey => request
+            .extensions()
+            .get::<ApiKeyFingerprint>()
+            .map(|fingerprint| format!("api_key:{}", fingerprint.0)),
+        RateLimitKey::Tenant => {
+            authenticated_tenant(request).map(|tenant| format!("tenant:{}", tenant))
+        }
+        RateLimitKey::Header { name } => request
+            .headers()
+            .get(name.as_str())
+            .and_then(|v| v.to_str().ok())
+            .map(|v| format!("header:{}:{}", name.to_lowercase(), v)),
+        RateLimitKey::Custom { name } => match extractors.get(name) {
+            Some(extractor) => extractor(request).map(|v| format!("{}:{}", name, v)),
+            None => {
+                warn!(name = name, "unknown rate limit key extractor");
+                None
+            }
+        },
+    }
+}
+
+impl<S> Middleware<S> {
+    /// keys of different tenants never collide, each tenant has its own quota
+    fn build_limiter_key(&self, rule: &Rule, request: &Request, ip: &str) -> String {
+        let limiter = &rule.limiter;
+        let mut key = String::from(RATE_LIMITER_KEY_BASE_PREFIX);
+        if let Some(tenant) = authenticated_tenant(request) {
+            key.push_str(&format!("tenant:{}:", tenant));
+        }
+        let mut dimensions = limiter.keys.iter().collect::<Vec<_>>();
+        if limiter.scope_ip && !dimensions.contains(&&RateLimitKey::Ip) {
+            dimensions.push(&RateLimitKey::Ip);
+        }
+        for dimension in dimensions {
+            let value = key_dimension(request, dimension, &self.extractors)
+                .unwrap_or_else(|| format!("ip:{}", ip));
+            key.push_str(&value);
+            key.push(':');
+        }
+        key.push_str(&rule.id);
+        key
+    }
+}
+
+/// `None` if the store failed
+async fn acquire_permits(
+    store: &dyn RateLimitStore,
+    limits: &[RateLimit<'_>],
+    ip: &str,
+) -> Option<Vec<RateLimitDecision>> {
+    match store.acquire(limits).await {
+        Ok(decisions) => {
+            for (limit, decision) in limits.iter().zip(decisions.iter()) {
+                info!(
+                    key = limit.key,
+                    ip = ip,
+                    acquired = decision.permitted,
+                    remaining = decision.remaining,
+                    "acquiring permit"
+                );
+            }
+            Some(decisions)
+        }
+        Err(e) => {
+            error!(
+                monotonic_counter.rate_limit_store_errors = 1_u64,
+                ip = ip,
+                limits = ?limits,
+                err = ?e,
+                "acquire permit error"
+            );
+            None
+        }
+    }
+}
+
+fn batch<'a>(rules: &'a [Rule], limits: &'a [(usize, String)]) -> Vec<RateLimit<'a>> {
+    limits
+        .iter()
+        .map(|(i, key)| RateLimit {
+            key,
+            limiter: &rules[*i].limiter,
+        })
+        .collect()
+}
+
+fn set_rate_limit_headers(headers: &mut HeaderMap, decision: &RateLimitDecision) {
+    headers.insert(header::RATE_LIMIT_LIMIT, decision.limit.into());
+    headers.insert(header::RATE_LIMIT_REMAINING, decision.remaining.into());
+    headers.insert(header::RATE_LIMIT_RESET, decision.reset_after_sec().into());
+    if !decision.permitted {
+        headers.insert(RETRY_AFTER, decision.retry_after_sec().into());
+    }
+}
+
+fn too_many_requests(decision: Option<&RateLimitDecision>) -> Response {
+    let mut response =
+        ErrorResponse::new_with_status_code(StatusCode::TOO_MANY_REQUESTS).into_response();
+    if let Some(decision) = decision {
+        set_rate_limit_headers(response.headers_mut(), decision);
+    }
+    response
+}
+
+impl<S> Service<Request> for Middleware<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        let path = request.uri().path().to_owned();
+        let ip = super::extract_ip(request.extensions());
+        let forward_key = request
+            .headers()
+            .get(header::X_RATE_LIMIT_FORWARD)
+            .map(|x| x.to_str().unwrap_or("").to_owned());
+        // indexes of matching rules with their keys
+        let mut limits = self
+            .rules
+            .iter()
+            .enumerate()
+            .filter(|(_, rule)| rule.matches(&request))
+            .map(|(i, rule)| (i, self.build_limiter_key(rule, &request, &ip)))
+            .collect::<Vec<_>>();
+        let rules = self.rules.clone();
+        let config = self.config.clone();
+        let backend = self.backend.clone();
+        let breaker = self.breaker.clone();
+        let fallback = self.fallback.clone();
+        // the ready service must be the one to be called
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            // given a forward key will bypass rate limiter
+            if let Some(forward_key) = forward_key {
+                let proceed = test_forward_key(
+                    &forward_key,
+                    &path,
+                    &config.forward_key_secret,
+                    backend.forward_keys.as_ref(),
+                )
+                .await;
+                debug!(proceed = proceed, "got forward key");
+                if !proceed {
+                    return Ok(too_many_requests(None));
+                }
+                return inner.call(request).await;
+            }
+            if limits.is_empty() {
+                return inner.call(request).await;
+            }
+            // every matching rule in a single round trip
+            let acquired = if breaker.allow() {
+                let acquired =
+                    acquire_permits(backend.limits.as_ref(), &batch(&rules, &limits), &ip).await;
+                match acquired {
+                    Some(_) => breaker.record_success(),
+                    None => breaker.record_failure(),
+                }
+                acquired
+            } else {
+                debug!("rate limit circuit open, store skipped");
+                None
+            };
+            let decisions = match acquired {
+                Some(decisions) => decisions,
+                // each rule follows its failure policy
+                None => {
+                    let policy = |i: usize| rules[i].limiter.on_failure;
+                    if let Some((i, _)) = limits
+                        .iter()
+                        .find(|(i, _)| policy(*i) == RateLimitFailurePolicy::Closed)
+                    {
+                        info!(rule = rules[*i].id, ip = ip, "rate limit store unavailable");
+                        return Ok(too_many_requests(None));
+                    }
+                    limits.retain(|(i, _)| policy(*i) == RateLimitFailurePolicy::Local);
+                    info!(
+                        monotonic_counter.rate_limit_degraded_requests = 1_u64,
+                        local = limits.len(),
+                        "rate limit degraded"
+                    );
+                    if limits.is_empty() {
+                        return inner.call(request).await;
+                    }
+                    let local = batch(&rules, &limits);
+                    match acquire_permits(fallback.as_ref(), &local, &ip).await {
+                        Some(decisions) => decisions,
+                        None => return Ok(too_many_requests(None)),
+                    }
+                }
+            };
+            if let Some(rejected) = decisions.iter().position(|d| !d.permitted) {
+                let rule = rules[limits[rejected].0].id.clone();
+                info!(rule = rule, ip = ip, "rate limited");
+                let decision = decisions[rejected];
+                let mut response = too_many_requests(Some(&decision));
+                response
+                    .extensions_mut()
+                    .insert(RateLimitRejection { rule, decision });
+                return Ok(response);
+            }
+            // the most restrictive decision is reported
+            let reported = decisions.into_iter().min_by_key(|d| d.remaining);
+            if let Some(decision) = reported {
+                request.extensions_mut().insert(decision);
+            }
+            let mut response: Response = inner.call(request).await?;
+            if let Some(decision) = reported {
+                set_rate_limit_headers(response.headers_mut(), &decision);
+            }
+            Ok(response)
+        })
+    }
+}
+
+impl<S> Middleware<S> {}
+
+/// lets a single request of `path` bypass rate limiters within `expire_sec`,
+/// e.g. a retry granted by an admin. sent in `X-Rate-Limit-Forward`.
+pub fn issue_forward_key(
+    path: &str,
+    secret: &str,
+    expire_sec: i64,
+) -> Result<String, anyhow::Error> {
+    SignedContent::new_with_expire(path.to_owned(), expire_sec).to_signed_string(secret)
+}
+
+/// forward keys are signed paths, each of them is accepted once
+async fn test_forward_key(
+    forward_key: &str,
+    path: &str,
+    secret: &str,
+    nonces: &dyn NonceStore,
+) -> bool {
+    let signed_content = match SignedContent::<String>::parse(forward_key, secret) {
+        Ok(signed_content) => signed_content,
+        Err(e) => {
+            info!(e = ?e, "parse signed key error");
+            return false;
+        }
+    };
+    debug!(
+        signed_content = signed_content.content,
+        path = path,
+        "inspect path permit"
+    );
+    if !signed_content.content.eq(path) {
+        return false;
+    }
+    let nonce = format!("{}{}", FORWARD_KEY_NONCE_PREFIX, signed_content.id);
+    nonces
+        .check_and_set(&nonce, signed_content.expire)
+        .await
+        .inspect_err(|e| error!(e = ?e, "consume forward key error"))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{MemoryStoreConfig, Pattern, RateLimitConfigBuilder, RateLimiterBuilder};
+    use crate::http::extracts::client_ip::ClientIp;
+    use axum::{body::Body, routing::get, Extension, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_in_memory_rate_limit() {
+        let config = RateLimitConfigBuilder::default()
+            .forward_key_secret("secret".to_owned())
+            .limiters(vec![RateLimiterBuilder::default()
+                .path("/api".to_owned())
+                .interval_sec(60)
+                .permits(1)
+                .build()
+                .unwrap()])
+            .build()
+            .unwrap();
+        let app = Router::new()
+            .route(
+                "/api/orders",
+                get(
+                    |decision: Option<Extension<RateLimitDecision>>| async move {
+                        decision
+                            .map(|d| d.remaining.to_string())
+                            .unwrap_or_default()
+                    },
+                ),
+            )
+            .layer(new(
+                config,
+                RateLimitBackend::new_in_memory(&MemoryStoreConfig::default()),
+            ));
+        let call = |forward_key: Option<&str>| {
+            let mut builder = Request::get("/api/orders");
+            if let Some(forward_key) = forward_key {
+                builder = builder.header(header::X_RATE_LIMIT_FORWARD, forward_key);
+            }
+            app.clone().oneshot(builder.body(Body::empty()).unwrap())
+        };
+        let response = call(None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::RATE_LIMIT_LIMIT], "1");
+        assert_eq!(response.headers()[header::RATE_LIMIT_REMAINING], "0");
+        assert_eq!(response.headers()[header::RATE_LIMIT_RESET], "60");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"0");
+        let response = call(None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "60");
+        let rejection = response.extensions().get::<RateLimitRejection>().unwrap();
+        assert_eq!(rejection.rule, "/api");
+        let forward_key = SignedContent::new("/api/orders".to_owned())
+            .to_signed_string("secret")
+            .unwrap();
+        assert_eq!(
+            call(Some(&forward_key)).await.unwrap().status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            call(Some(&forward_key)).await.unwrap().status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[tokio::test]
+    async fn test_key_dimensions() {
+        let limiter = |keys| {
+            RateLimiterBuilder::default()
+                .path("/".to_owned())
+                .interval_sec(60)
+                .permits(1)
+                .keys(keys)
+                .build()
+                .unwrap()
+        };
+        let config = RateLimitConfigBuilder::default()
+            .forward_key_secret("secret".to_owned())
+            .limiters(vec![
+                limiter(vec![RateLimitKey::User]),
+                limiter(vec![RateLimitKey::Custom {
+                    name: "device".to_owned(),
+                }]),
+            ])
+            .build()
+            .unwrap();
+        let app = Router::new().route("/", get(|| async {})).layer(
+            new(
+                config,
+                RateLimitBackend::new_in_memory(&MemoryStoreConfig::default()),
+            )
+            .key_extractor("device", |request| {
+                request
+                    .headers()
+                    .get("X-Device")
+                    .and_then(|v| v.to_str().ok())
+                    .map(str::to_owned)
+            }),
+        );
+        let call = |user_id: Option<i64>, device: &str| {
+            let mut request = Request::get("/")
+                .header("X-Device", device)
+                .body(Body::empty())
+                .unwrap();
+            request
+                .extensions_mut()
+                .insert(ClientIp("10.0.0.1".parse().unwrap()));
+            if let Some(user_id) = user_id {
+                request.extensions_mut().insert(TokenUser {
+                    user_id,
+                    ..Default::default()
+                });
+            }
+            let app = app.clone();
+            async move { app.oneshot(request).await.unwrap().status() }
+        };
+        // users behind the same NAT
+        assert_eq!(call(Some(1), "a").await, StatusCode::OK);
+        assert_eq!(call(Some(2), "b").await, StatusCode::OK);
+        assert_eq!(call(Some(1), "c").await, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(call(Some(3), "a").await, StatusCode::TOO_MANY_REQUESTS);
+        // anonymous callers share the quota of their ip
+        assert_eq!(call(None, "d").await, StatusCode::OK);
+        assert_eq!(call(None, "e").await, StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_route_matching() {
+        let config = RateLimitConfigBuilder::default()
+            .forward_key_secret("secret".to_owned())
+            .limiters(vec![
+                RateLimiterBuilder::default()
+                    .route(Some("/users/:id/orders".to_owned()))
+                    .methods(vec!["post".to_owned()])
+                    .interval_sec(60)
+                    .permits(1)
+                    .build()
+                    .unwrap(),
+                RateLimiterBuilder::default()
+                    .pattern(Some(Pattern::new(r"^/files/\d+$").unwrap()))
+                    .interval_sec(60)
+                    .permits(1)
+                    .build()
+                    .unwrap(),
+            ])
+            .build()
+            .unwrap();
+        let app = Router::new()
+            .route("/users/:id/orders", get(|| async {}).post(|| async {}))
+            .route("/files/:name", get(|| async {}))
+            .layer(new(
+                config,
+                RateLimitBackend::new_in_memory(&MemoryStoreConfig::default()),
+            ));
+        let call = |method: Method, uri: &str| {
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move { app.oneshot(request).await.unwrap().status() }
+        };
+        assert_eq!(call(Method::POST, "/users/1/orders").await, StatusCode::OK);
+        assert_eq!(
+            call(Method::POST, "/users/2/orders").await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(call(Method::GET, "/users/1/orders").await, StatusCode::OK);
+        assert_eq!(call(Method::GET, "/files/1").await, StatusCode::OK);
+        assert_eq!(
+            call(Method::GET, "/files/2").await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(call(Method::GET, "/files/readme").await, StatusCode::OK);
+    }
+
+    struct FailingStore;
+
+    impl RateLimitStore for FailingStore {
+        fn acquire<'a>(
+            &'a self,
+            _limits: &'a [RateLimit<'a>],
+        ) -> BoxFuture<'a, Result<Vec<RateLimitDecision>, anyhow::Error>> {
+            Box::pin(async { Err(anyhow::anyhow!("unavailable")) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failure_policy() {
+        let limiter = |path: &str, on_failure| {
+            RateLimiterBuilder::default()
+                .path(path.to_owned())
+                .interval_sec(60)
+                .permits(1)
+                .on_failure(on_failure)
+                .build()
+                .unwrap()
+        };
+        let config = RateLimitConfigBuilder::default()
+            .forward_key_secret("secret".to_owned())
+            .limiters(vec![
+                limiter("/open", RateLimitFailurePolicy::Open),
+                limiter("/closed", RateLimitFailurePolicy::Closed),
+                limiter("/local", RateLimitFailurePolicy::Local),
+            ])
+            .build()
+            .unwrap();
+        let backend = RateLimitBackend {
+            limits: Arc::new(FailingStore),
+            ..RateLimitBackend::new_in_memory(&MemoryStoreConfig::default())
+        };
+        let layer = new(config, backend);
+        let breaker = layer.breaker.clone();
+        let app = Router::new()
+            .route("/open", get(|| async {}))
+            .route("/closed", get(|| async {}))
+            .route("/local", get(|| async {}))
+            .layer(layer);
+        let call = |path: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = Request::get(path).body(Body::empty()).unwrap();
+                app.oneshot(request).await.unwrap().status()
+            }
+        };
+        assert_eq!(call("/open").await, StatusCode::OK);
+        assert_eq!(call("/open").await, StatusCode::OK);
+        assert_eq!(call("/closed").await, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(call("/local").await, StatusCode::OK);
+        assert_eq!(call("/local").await, StatusCode::TOO_MANY_REQUESTS);
+        // 5 consecutive errors by default
+        assert!(breaker.is_open());
+        assert_eq!(call("/open").await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_forward_keys() {
+        let config = RateLimitConfigBuilder::default()
+            .forward_key_secret("secret".to_owned())
+            .limiters(vec![RateLimiterBuilder::default()
+                .path("/api".to_owned())
+                .interval_sec(60)
+                .permits(0)
+                .build()
+                .unwrap()])
+            .build()
+            .unwrap();
+        let layer = new(
+            config,
+            RateLimitBackend::new_in_memory(&MemoryStoreConfig::default()),
+        );
+        let forward_key = layer.issue_forward_key("/api/orders").unwrap();
+        let app = Router::new()
+            .route("/api/orders", get(|| async {}))
+            .route("/api/users", get(|| async {}))
+            .layer(layer);
+        let call = |path: &'static str, forward_key: String| {
+            let app = app.clone();
+            async move {
+                let request = Request::get(path)
+                    .header(header::X_RATE_LIMIT_FORWARD, forward_key)
+                    .body(Body::empty())
+                    .unwrap();
+                app.oneshot(request).await.unwrap().status()
+            }
+        };
+        // bound to its path, not consumed by other ones
+        assert_eq!(
+            call("/api/users", forward_key.clone()).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            call("/api/orders", forward_key.clone()).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            call("/api/orders", forward_key).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        let forged = issue_forward_key("/api/orders", "other", 30).unwrap();
+        assert_eq!(
+            call("/api/orders", forged).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        let expired = issue_forward_key("/api/orders", "secret", 0).unwrap();
+        assert_eq!(
+            call("/api/orders", expired).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unverified_api_keys() {
+        let config = RateLimitConfigBuilder::default()
+            .forward_key_secret("secret".to_owned())
+            .limiters(vec![RateLimiterBuilder::default()
+                .path("/".to_owned())
+                .interval_sec(60)
+                .permits(1)
+                .keys(vec![RateLimitKey::ApiKey])
+                .build()
+                .unwrap()])
+            .build()
+            .unwrap();
+        let app = Router::new().route("/", get(|| async {})).layer(new(
+            config,
+            RateLimitBackend::new_in_memory(&MemoryStoreConfig::default()),
+        ));
+        let call = |fingerprint: Option<&str>| {
+            let mut request = Request::get("/")
+                .header(header::X_ACCESS_ID, xid::new().to_string())
+                .body(Body::empty())
+                .unwrap();
+            request
+                .extensions_mut()
+                .insert(ClientIp("10.0.0.1".parse().unwrap()));
+            if let Some(fingerprint) = fingerprint {
+                request
+                    .extensions_mut()
+                    .insert(ApiKeyFingerprint(fingerprint.to_owned()));
+            }
+            let app = app.clone();
+            async move { app.oneshot(request).await.unwrap().status() }
+        };
+        // random unverified keys share the quota of their ip
+        assert_eq!(call(None).await, StatusCode::OK);
+        assert_eq!(call(None).await, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(call(Some("a")).await, StatusCode::OK);
+        assert_eq!(call(Some("a")).await, StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_unverified_tenants() {
+        let limiter = |keys| {
+            RateLimiterBuilder::default()
+                .path("/".to_owned())
+                .interval_sec(60)
+                .permits(1)
+                .keys(keys)
+                .build()
+                .unwrap()
+        };
+        let config = RateLimitConfigBuilder::default()
+            .forward_key_secret("secret".to_owned())
+            .limiters(vec![limiter(vec![]), limiter(vec![RateLimitKey::Tenant])])
+            .build()
+            .unwrap();
+        let tenant = crate::http::middlewares::tenant::TenantConfigBuilder::default()
+            .sources(vec![
+                crate::http::middlewares::tenant::TenantSource::Header {
+                    name: header::X_TENANT_ID.to_owned(),
+                },
+            ])
+            .build()
+            .unwrap();
+        let app = Router::new()
+            .route("/", get(|| async {}))
+            .layer(new(
+                config,
+                RateLimitBackend::new_in_memory(&MemoryStoreConfig::default()),
+            ))
+            .layer(crate::http::middlewares::tenant::new(tenant));
+        let call = || {
+            let mut request = Request::get("/")
+                .header(header::X_TENANT_ID, xid::new().to_string())
+                .body(Body::empty())
+                .unwrap();
+            request
+                .extensions_mut()
+                .insert(ClientIp("10.0.0.1".parse().unwrap()));
+            app.clone().oneshot(request)
+        };
+        // a new tenant for each request is no new quota
+        assert_eq!(call().await.unwrap().status(), StatusCode::OK);
+        assert_eq!(
+            call().await.unwrap().status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+}
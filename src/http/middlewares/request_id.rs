@@ -49,10 +49,10 @@ where
                 .append(header::X_REQUEST_ID, header_val);
         }
         let future = self.inner.call(request);
-        return Box::pin(async move {
+        Box::pin(async move {
             let response: Response = future.await?;
             Ok(response)
-        });
+        })
     }
 }
 
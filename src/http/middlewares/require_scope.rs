@@ -0,0 +1,134 @@
+use crate::http::authenticator::{BearerError, Challenge, DEFAULT_REALM};
+use crate::http::user_token::TokenUser;
+use axum::extract::Request;
+use axum::response::{IntoResponse, Response};
+use futures_util::future::BoxFuture;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+use tracing::info;
+
+/// must be layered inside an authentication layer.
+/// anonymous requests get 401, `TokenUser`s lacking any of the scopes get 403 `insufficient_scope`.
+#[derive(Clone)]
+pub struct MLayer {
+    realm: Arc<str>,
+    scopes: Arc<Vec<String>>,
+}
+
+pub fn new(scopes: Vec<String>) -> MLayer {
+    new_with_realm(DEFAULT_REALM, scopes)
+}
+
+pub fn new_with_realm(realm: &str, scopes: Vec<String>) -> MLayer {
+    MLayer {
+        realm: realm.into(),
+        scopes: Arc::new(scopes),
+    }
+}
+
+impl<S> Layer<S> for MLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Middleware {
+            inner,
+            realm: self.realm.clone(),
+            scopes: self.scopes.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    inner: S,
+    realm: Arc<str>,
+    scopes: Arc<Vec<String>>,
+}
+
+impl<S> Service<Request> for Middleware<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let challenge = match request.extensions().get::<TokenUser>() {
+            None => Some(Challenge::bearer(&self.realm)),
+            Some(token_user) if !self.scopes.iter().all(|s| token_user.has_scope(s)) => {
+                info!(
+                    user_id = token_user.user_id,
+                    required = ?self.scopes,
+                    "insufficient scope"
+                );
+                let scope = self.scopes.join(" ");
+                Some(
+                    Challenge::bearer(&self.realm)
+                        .error(BearerError::InsufficientScope, "insufficient scope")
+                        .scope(&scope),
+                )
+            }
+            Some(_) => None,
+        };
+        if let Some(challenge) = challenge {
+            return Box::pin(async move { Ok(challenge.into_response()) });
+        }
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let response: Response = future.await?;
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{header, StatusCode};
+    use axum::{body::Body, routing::get, Extension, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_insufficient_scope() {
+        let app = |scopes: Vec<String>| {
+            Router::new()
+                .route("/", get(|| async {}))
+                .layer(new(vec!["orders:write".to_owned()]))
+                .layer(Extension(TokenUser {
+                    user_id: 1,
+                    scopes,
+                    ..Default::default()
+                }))
+        };
+        let request = || Request::get("/").body(Body::empty()).unwrap();
+        let response = app(vec!["orders:read".to_owned()])
+            .oneshot(request())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            response.headers().get(header::WWW_AUTHENTICATE).unwrap(),
+            "Bearer realm=\"api\", error=\"insufficient_scope\", \
+             error_description=\"insufficient scope\", scope=\"orders:write\""
+        );
+        let response = app(vec!["orders:write".to_owned()])
+            .oneshot(request())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = Router::new()
+            .route("/", get(|| async {}))
+            .layer(new(vec![]))
+            .oneshot(request())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,223 @@
+use crate::http::extracts::tenant::Tenant;
+use crate::http::header::X_TENANT_ID;
+use crate::http::user_token::TokenUser;
+use crate::utils::http_error_handler::ErrorResponse;
+use axum::extract::Request;
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use derive_builder::Builder;
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+use tracing::warn;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TenantSource {
+    /// `tenant_id` of the authenticated `TokenUser`
+    Token,
+    /// `acme.example.com` is tenant `acme` for base domain `example.com`
+    Subdomain { base_domain: String },
+    Header {
+        #[serde(default = "default_tenant_header")]
+        name: String,
+    },
+}
+
+fn default_tenant_header() -> String {
+    X_TENANT_ID.to_owned()
+}
+
+#[derive(Debug, Clone, Deserialize, Builder)]
+pub struct TenantConfig {
+    /// tried in order, the first one found wins
+    pub sources: Vec<TenantSource>,
+    /// reject requests without a tenant
+    #[builder(default)]
+    #[serde(default)]
+    pub required: bool,
+}
+
+impl TenantSource {
+    fn resolve(&self, request: &Request) -> Option<String> {
+        match self {
+            TenantSource::Token => request
+                .extensions()
+                .get::<TokenUser>()
+                .and_then(|user| user.tenant_id.clone()),
+            TenantSource::Subdomain { base_domain } => {
+                let host = request.headers().get(header::HOST)?.to_str().ok()?;
+                let host = host.split(':').next()?;
+                let tenant = host.strip_suffix(base_domain.as_str())?.strip_suffix('.')?;
+                (!tenant.is_empty() && !tenant.contains('.')).then(|| tenant.to_lowercase())
+            }
+            TenantSource::Header { name } => request
+                .headers()
+                .get(name.as_str())
+                .and_then(|v| v.to_str().ok())
+                .filter(|v| !v.is_empty())
+                .map(str::to_owned),
+        }
+    }
+}
+
+/// must be layered inside an authentication layer.
+/// authenticated users may only act in their own tenant, whatever source resolved it.
+#[derive(Clone)]
+pub struct MLayer {
+    config: Arc<TenantConfig>,
+}
+
+pub fn new(config: TenantConfig) -> MLayer {
+    MLayer {
+        config: Arc::new(config),
+    }
+}
+
+impl<S> Layer<S> for MLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Middleware {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    inner: S,
+    config: Arc<TenantConfig>,
+}
+
+impl<S> Service<Request> for Middleware<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        let tenant = self
+            .config
+            .sources
+            .iter()
+            .find_map(|source| source.resolve(&request));
+        let rejected = match (&tenant, request.extensions().get::<TokenUser>()) {
+            (None, _) if self.config.required => {
+                Some(ErrorResponse::new_with_message("tenant required"))
+            }
+            (Some(tenant), Some(user)) if user.tenant_id.as_ref() != Some(tenant) => {
+                warn!(
+                    user_id = user.user_id,
+                    user_tenant = user.tenant_id,
+                    tenant = tenant,
+                    "cross tenant request"
+                );
+                Some(ErrorResponse::new_forb())
+            }
+            _ => None,
+        };
+        if let Some(rejected) = rejected {
+            return Box::pin(async move { Ok(rejected.into_response()) });
+        }
+        if let Some(tenant) = tenant {
+            let _ = request.extensions_mut().insert(Tenant(tenant));
+        }
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let response: Response = future.await?;
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use axum::{body::Body, routing::get, Extension, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_tenant_resolution() {
+        let config = TenantConfigBuilder::default()
+            .sources(vec![
+                TenantSource::Token,
+                TenantSource::Subdomain {
+                    base_domain: "example.com".to_owned(),
+                },
+                TenantSource::Header {
+                    name: X_TENANT_ID.to_owned(),
+                },
+            ])
+            .required(true)
+            .build()
+            .unwrap();
+        let app = |user: Option<TokenUser>| {
+            let router = Router::new()
+                .route("/", get(|tenant: Tenant| async move { tenant.0 }))
+                .layer(new(config.clone()));
+            match user {
+                Some(user) => router.layer(Extension(user)),
+                None => router,
+            }
+        };
+        let acme = TokenUser {
+            user_id: 1,
+            tenant_id: Some("acme".to_owned()),
+            ..Default::default()
+        };
+        let request = |host: &str, tenant: Option<&str>| {
+            let mut builder = Request::get("/").header(header::HOST, host);
+            if let Some(tenant) = tenant {
+                builder = builder.header(X_TENANT_ID, tenant);
+            }
+            builder.body(Body::empty()).unwrap()
+        };
+
+        let response = app(Some(acme.clone()))
+            .oneshot(request("api.example.org", Some("globex")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"acme");
+
+        let response = app(None)
+            .oneshot(request("globex.example.com:8080", None))
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"globex");
+
+        let cross_tenant = TokenUser {
+            tenant_id: None,
+            ..acme
+        };
+        let response = app(Some(cross_tenant))
+            .oneshot(request("globex.example.com", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = app(None)
+            .oneshot(request("example.com", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
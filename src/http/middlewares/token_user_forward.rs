@@ -0,0 +1,72 @@
+use crate::http::header;
+use crate::http::user_token::{SignedTokenUserConfig, TokenUser};
+use axum::extract::Request;
+use axum::http::HeaderValue;
+use axum::response::Response;
+use futures_util::future::BoxFuture;
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+use tracing::error;
+
+/// gateway side. must be layered inside the authentication layer.
+/// forwards the authenticated `TokenUser` to upstreams as a signed `X-Token-User`,
+/// whatever `X-Token-User` sent by clients is dropped.
+#[derive(Clone)]
+pub struct MLayer {
+    config: SignedTokenUserConfig,
+}
+
+pub fn new(config: SignedTokenUserConfig) -> MLayer {
+    MLayer { config }
+}
+
+impl<S> Layer<S> for MLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Middleware {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    inner: S,
+    config: SignedTokenUserConfig,
+}
+
+impl<S> Service<Request> for Middleware<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        request.headers_mut().remove(header::X_TOKEN_USER);
+        if let Some(token_user) = request.extensions().get::<TokenUser>() {
+            match token_user
+                .to_signed_header(&self.config)
+                .and_then(|signed| Ok(HeaderValue::from_str(&signed)?))
+            {
+                Ok(signed) => {
+                    request.headers_mut().insert(header::X_TOKEN_USER, signed);
+                }
+                Err(e) => error!(err = e.to_string(), "sign token user error"),
+            }
+        }
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let response: Response = future.await?;
+            Ok(response)
+        })
+    }
+}
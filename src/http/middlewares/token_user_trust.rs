@@ -0,0 +1,155 @@
+use crate::auth::impersonation;
+use crate::http::header;
+use crate::http::user_token::{SignedTokenUserConfig, TokenUser};
+use axum::extract::Request;
+use axum::response::Response;
+use futures_util::future::BoxFuture;
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+use tracing::warn;
+
+/// upstream side of a gateway deployment.
+/// only a correctly signed and unexpired `X-Token-User` becomes the `TokenUser` extension,
+/// forged ones are stripped and the request goes on anonymously.
+#[derive(Clone)]
+pub struct MLayer {
+    config: SignedTokenUserConfig,
+}
+
+pub fn new(config: SignedTokenUserConfig) -> MLayer {
+    MLayer { config }
+}
+
+impl<S> Layer<S> for MLayer {
+    type Service = Middleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Middleware {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Middleware<S> {
+    inner: S,
+    config: SignedTokenUserConfig,
+}
+
+impl<S> Service<Request> for Middleware<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        let mut impersonated = None;
+        if let Some(signed) = request.headers_mut().remove(header::X_TOKEN_USER) {
+            match signed
+                .to_str()
+                .map_err(anyhow::Error::from)
+                .and_then(|signed| TokenUser::parse_signed_header(signed, &self.config))
+            {
+                Ok(token_user) => {
+                    if token_user.is_impersonated() {
+                        impersonated = Some((
+                            token_user.clone(),
+                            super::extract_ip(request.extensions()),
+                            request.method().clone(),
+                            request.uri().path().to_owned(),
+                        ));
+                    }
+                    request.extensions_mut().insert(token_user);
+                }
+                Err(e) => warn!(err = e.to_string(), "forged x-token-user header stripped"),
+            }
+        }
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let response: Response = future.await?;
+            if let Some((token_user, ip, method, path)) = impersonated {
+                impersonation::audit(&token_user, &ip, &method, &path, response.status());
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::middlewares::token_user_forward;
+    use crate::http::user_token::SignedTokenUserConfigBuilder;
+    use axum::http::HeaderMap;
+    use axum::{body::Body, routing::get, Extension, Router};
+    use tower::ServiceExt;
+
+    async fn body_string(response: Response) -> String {
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_token_user_propagation() {
+        let config = SignedTokenUserConfigBuilder::default()
+            .secret("secret".to_owned())
+            .build()
+            .unwrap();
+        // echo the forwarded header
+        let gateway = Router::new()
+            .route(
+                "/",
+                get(|headers: HeaderMap| async move {
+                    headers
+                        .get(header::X_TOKEN_USER)
+                        .map(|v| v.to_str().unwrap().to_owned())
+                        .unwrap_or_default()
+                }),
+            )
+            .layer(token_user_forward::new(config.clone()))
+            .layer(Extension(TokenUser {
+                user_id: 9,
+                ..Default::default()
+            }));
+        let upstream = Router::new()
+            .route(
+                "/",
+                get(|user: Option<Extension<TokenUser>>| async move {
+                    user.map(|u| u.user_id.to_string()).unwrap_or_default()
+                }),
+            )
+            .layer(new(config));
+
+        let request = Request::get("/")
+            .header(header::X_TOKEN_USER, "forged")
+            .body(Body::empty())
+            .unwrap();
+        let signed = body_string(gateway.oneshot(request).await.unwrap()).await;
+        assert_ne!(signed, "forged");
+
+        let request = Request::get("/")
+            .header(header::X_TOKEN_USER, &signed)
+            .body(Body::empty())
+            .unwrap();
+        let response = upstream.clone().oneshot(request).await.unwrap();
+        assert_eq!(body_string(response).await, "9");
+
+        let request = Request::get("/")
+            .header(header::X_TOKEN_USER, "forged")
+            .body(Body::empty())
+            .unwrap();
+        let response = upstream.oneshot(request).await.unwrap();
+        assert_eq!(body_string(response).await, "");
+    }
+}
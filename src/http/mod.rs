@@ -1,5 +1,7 @@
+pub mod authenticator;
+pub mod cookie;
 pub mod extracts;
 pub mod server;
 pub mod user_token;
 pub mod header;
-mod middlewares;
+pub mod middlewares;
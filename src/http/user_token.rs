@@ -1,7 +1,61 @@
+use crate::utils::hash::SignedContent;
+use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default, Hash)]
 pub struct TokenUser {
     #[serde(rename = "user_id")]
     pub user_id: i64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    /// set when someone else is acting as this user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+}
+
+/// RFC 8693 `act` claim, the party acting on behalf of the subject
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
+pub struct Actor {
+    pub sub: String,
+}
+
+impl TokenUser {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+
+    pub fn is_impersonated(&self) -> bool {
+        self.act.is_some()
+    }
+
+    /// value of `X-Token-User` forwarded by gateways
+    pub fn to_signed_header(
+        &self,
+        config: &SignedTokenUserConfig,
+    ) -> Result<String, anyhow::Error> {
+        SignedContent::new_with_expire(self.clone(), config.expire_sec)
+            .to_signed_string(&config.secret)
+    }
+
+    pub fn parse_signed_header(
+        signed: &str,
+        config: &SignedTokenUserConfig,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(SignedContent::<TokenUser>::parse(signed, &config.secret)?.content)
+    }
+}
+
+/// shared by the gateway and its upstreams
+#[derive(Clone, Deserialize, Builder)]
+pub struct SignedTokenUserConfig {
+    pub secret: String,
+    #[builder(default = "SignedContent::<TokenUser>::DEFAULT_EXPIRATION")]
+    #[serde(default = "default_signed_token_user_expire_sec")]
+    pub expire_sec: i64,
+}
+
+fn default_signed_token_user_expire_sec() -> i64 {
+    SignedContent::<TokenUser>::DEFAULT_EXPIRATION
 }
@@ -1,3 +1,8 @@
 
 pub mod utils;
+pub mod config;
+pub mod rate_limit;
 pub mod http;
+pub mod auth;
+#[cfg(feature = "redis")]
+pub mod redis;
@@ -0,0 +1,123 @@
+use crate::config::CircuitBreakerConfig;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+#[derive(Default)]
+struct State {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// counts consecutive store errors, the store is skipped for a cooldown once too many of them happened
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: &CircuitBreakerConfig) -> Self {
+        Self {
+            failure_threshold: config.failure_threshold.max(1),
+            cooldown: Duration::from_secs(config.cooldown_sec),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// a poisoned breaker is reset, the store is tried again
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| {
+            warn!("rate limit circuit breaker poisoned, circuit closed");
+            let mut state = poisoned.into_inner();
+            *state = State::default();
+            self.state.clear_poison();
+            state
+        })
+    }
+
+    /// once the cooldown is over, a single call is allowed to probe the store
+    pub fn allow(&self) -> bool {
+        let mut state = self.state();
+        match state.open_until {
+            None => true,
+            Some(until) if Instant::now() < until => false,
+            Some(_) => {
+                state.open_until = Some(Instant::now() + self.cooldown);
+                true
+            }
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.state().open_until.is_some()
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state();
+        if state.open_until.take().is_some() {
+            info!("rate limit store recovered, circuit closed");
+        }
+        state.failures = 0;
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state();
+        state.failures = state.failures.saturating_add(1);
+        if state.failures >= self.failure_threshold {
+            if state.open_until.is_none() {
+                warn!(
+                    failures = state.failures,
+                    cooldown_sec = self.cooldown.as_secs(),
+                    "rate limit store failing, circuit opened"
+                );
+            }
+            state.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CircuitBreakerConfigBuilder;
+
+    #[test]
+    fn test_circuit_breaker() {
+        let config = CircuitBreakerConfigBuilder::default()
+            .failure_threshold(2)
+            .cooldown_sec(0)
+            .build()
+            .unwrap();
+        let breaker = CircuitBreaker::new(&config);
+        breaker.record_failure();
+        assert!(!breaker.is_open());
+        breaker.record_failure();
+        assert!(breaker.is_open());
+        // a zero cooldown is over at once, the probe reopens it on failure
+        assert!(breaker.allow());
+        breaker.record_failure();
+        assert!(breaker.is_open());
+        breaker.record_success();
+        assert!(!breaker.is_open());
+
+        let config = CircuitBreakerConfigBuilder::default()
+            .failure_threshold(1)
+            .cooldown_sec(60)
+            .build()
+            .unwrap();
+        let breaker = CircuitBreaker::new(&config);
+        assert!(breaker.allow());
+        breaker.record_failure();
+        assert!(!breaker.allow());
+        std::thread::scope(|scope| {
+            let poisoning = scope.spawn(|| {
+                let _state = breaker.state.lock();
+                panic!("poisoning the breaker");
+            });
+            assert!(poisoning.join().is_err());
+        });
+        assert!(!breaker.is_open());
+        assert!(breaker.allow());
+    }
+}
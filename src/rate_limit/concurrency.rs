@@ -0,0 +1,239 @@
+use crate::config::{AdaptiveLimitAlgorithm, AdaptiveLimitConfig};
+use std::pin::pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::debug;
+
+struct State {
+    in_flight: usize,
+    limit: f64,
+    /// lowest latency seen, in ms
+    min_latency: f64,
+    /// moving average of latencies, in ms
+    latency: f64,
+}
+
+/// admits up to `limit` requests in flight, others wait for a slot
+pub struct ConcurrencyGate {
+    state: Mutex<State>,
+    released: Notify,
+    adaptive: Option<AdaptiveLimitConfig>,
+    max_limit: f64,
+}
+
+impl ConcurrencyGate {
+    pub fn new(limit: usize) -> Self {
+        Self::new_adaptive(limit, None)
+    }
+
+    /// the limit starts at `limit` then follows `adaptive`
+    pub fn new_adaptive(limit: usize, adaptive: Option<AdaptiveLimitConfig>) -> Self {
+        let max_limit = adaptive
+            .as_ref()
+            .map(|adaptive| adaptive.max_limit)
+            .filter(|max_limit| *max_limit > 0)
+            .unwrap_or(limit);
+        Self {
+            state: Mutex::new(State {
+                in_flight: 0,
+                limit: limit.max(1) as f64,
+                min_latency: f64::MAX,
+                latency: 0.0,
+            }),
+            released: Notify::new(),
+            adaptive,
+            max_limit: max_limit.max(1) as f64,
+        }
+    }
+
+    /// the state is consistent between statements, a panic elsewhere does not break it
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.state().limit as usize
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.state().in_flight
+    }
+
+    pub fn try_acquire(self: &Arc<Self>) -> Option<ConcurrencyPermit> {
+        let mut state = self.state();
+        if state.in_flight >= state.limit as usize {
+            return None;
+        }
+        state.in_flight += 1;
+        Some(ConcurrencyPermit {
+            gate: self.clone(),
+            started_at: Instant::now(),
+            succeeded: None,
+        })
+    }
+
+    /// `None` if no slot was released before `deadline`
+    pub async fn acquire(self: &Arc<Self>, deadline: Instant) -> Option<ConcurrencyPermit> {
+        loop {
+            // registered before trying, releases in between are not missed
+            let mut released = pin!(self.released.notified());
+            released.as_mut().enable();
+            if let Some(permit) = self.try_acquire() {
+                return Some(permit);
+            }
+            tokio::time::timeout_at(deadline.into(), released)
+                .await
+                .ok()?;
+        }
+    }
+
+    fn release(&self, sample: Option<(Duration, bool)>) {
+        let mut state = self.state();
+        if let (Some(adaptive), Some((latency, succeeded))) = (&self.adaptive, sample) {
+            let latency = latency.as_secs_f64() * 1000.0;
+            let limit = match adaptive.algorithm {
+                AdaptiveLimitAlgorithm::Aimd => {
+                    if !succeeded || latency > adaptive.latency_threshold_ms as f64 {
+                        state.limit * adaptive.backoff_ratio
+                    } else if state.in_flight as f64 >= state.limit {
+                        // only raised while the limit is reached
+                        state.limit + 1.0
+                    } else {
+                        state.limit
+                    }
+                }
+                AdaptiveLimitAlgorithm::Gradient => {
+                    state.min_latency = state.min_latency.min(latency);
+                    state.latency = if state.latency == 0.0 {
+                        latency
+                    } else {
+                        state.latency * (1.0 - adaptive.smoothing) + latency * adaptive.smoothing
+                    };
+                    let gradient = (state.min_latency / state.latency).clamp(0.5, 1.0);
+                    // room for a queue keeps probing for a higher limit
+                    let limit = state.limit * gradient + state.limit.sqrt();
+                    state.limit * (1.0 - adaptive.smoothing) + limit * adaptive.smoothing
+                }
+            };
+            let limit = limit.clamp(adaptive.min_limit.max(1) as f64, self.max_limit);
+            if limit as usize != state.limit as usize {
+                debug!(
+                    limit = limit as usize,
+                    latency = latency,
+                    "concurrency limit changed"
+                );
+            }
+            state.limit = limit;
+        }
+        state.in_flight -= 1;
+        drop(state);
+        self.released.notify_waiters();
+    }
+}
+
+/// a slot of a `ConcurrencyGate`, released on drop
+pub struct ConcurrencyPermit {
+    gate: Arc<ConcurrencyGate>,
+    started_at: Instant,
+    succeeded: Option<bool>,
+}
+
+impl ConcurrencyPermit {
+    /// the latency of completed requests adjusts adaptive limits, cancelled ones are ignored
+    pub fn complete(mut self, succeeded: bool) {
+        self.succeeded = Some(succeeded);
+    }
+}
+
+impl Drop for ConcurrencyPermit {
+    fn drop(&mut self) {
+        let sample = self
+            .succeeded
+            .map(|succeeded| (self.started_at.elapsed(), succeeded));
+        self.gate.release(sample);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AdaptiveLimitConfigBuilder;
+
+    #[tokio::test]
+    async fn test_gate() {
+        let gate = Arc::new(ConcurrencyGate::new(1));
+        let permit = gate.try_acquire().unwrap();
+        assert!(gate.try_acquire().is_none());
+        let deadline = Instant::now() + Duration::from_millis(20);
+        assert!(gate.acquire(deadline).await.is_none());
+        let waiting = tokio::spawn({
+            let gate = gate.clone();
+            async move {
+                let deadline = Instant::now() + Duration::from_secs(1);
+                gate.acquire(deadline).await.is_some()
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        drop(permit);
+        assert!(waiting.await.unwrap());
+        assert_eq!(gate.in_flight(), 0);
+    }
+
+    #[test]
+    fn test_aimd() {
+        let adaptive = AdaptiveLimitConfigBuilder::default()
+            .min_limit(2)
+            .max_limit(4)
+            .backoff_ratio(0.5)
+            .build()
+            .unwrap();
+        let gate = Arc::new(ConcurrencyGate::new_adaptive(2, Some(adaptive)));
+        let permits = [gate.try_acquire().unwrap(), gate.try_acquire().unwrap()];
+        for permit in permits {
+            permit.complete(true);
+        }
+        // raised once, the gate was full on the first completion only
+        assert_eq!(gate.limit(), 3);
+        for _ in 0..4 {
+            let permits = (0..gate.limit())
+                .map(|_| gate.try_acquire().unwrap())
+                .collect::<Vec<_>>();
+            permits.into_iter().for_each(|permit| permit.complete(true));
+        }
+        assert_eq!(gate.limit(), 4);
+        gate.try_acquire().unwrap().complete(false);
+        assert_eq!(gate.limit(), 2);
+        gate.try_acquire().unwrap().complete(false);
+        assert_eq!(gate.limit(), 2);
+    }
+
+    #[test]
+    fn test_gradient() {
+        let adaptive = AdaptiveLimitConfigBuilder::default()
+            .algorithm(AdaptiveLimitAlgorithm::Gradient)
+            .max_limit(20)
+            .smoothing(0.5)
+            .build()
+            .unwrap();
+        let gate = Arc::new(ConcurrencyGate::new_adaptive(20, Some(adaptive)));
+        let complete = |latency_ms: u64| {
+            let mut permit = gate.try_acquire().unwrap();
+            permit.started_at = Instant::now() - Duration::from_millis(latency_ms);
+            permit.complete(true);
+        };
+        complete(10);
+        assert_eq!(gate.limit(), 20);
+        // latencies above the lowest one shrink the limit
+        for _ in 0..10 {
+            complete(100);
+        }
+        let shrunk = gate.limit();
+        assert!(shrunk < 10, "{}", shrunk);
+        // and it recovers once they fall back
+        for _ in 0..30 {
+            complete(10);
+        }
+        assert_eq!(gate.limit(), 20);
+    }
+}
@@ -0,0 +1,289 @@
+use super::{RateLimit, RateLimitDecision, RateLimitStore};
+use crate::config::{MemoryStoreConfig, RateLimitAlgorithm, RateLimiter};
+use anyhow::anyhow;
+use futures_util::future::BoxFuture;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+#[derive(Default)]
+struct Shard {
+    entries: HashMap<String, Entry>,
+    cleaned_at: i64,
+}
+
+struct Entry {
+    state: State,
+    expire_at: i64,
+}
+
+/// times are in millis
+#[derive(Clone)]
+enum State {
+    Log(VecDeque<i64>),
+    Window {
+        start: i64,
+        count: u64,
+        previous: u64,
+    },
+    Bucket {
+        tokens: f64,
+        updated_at: i64,
+    },
+    Gcra {
+        tat: f64,
+    },
+}
+
+impl State {
+    fn new(algorithm: RateLimitAlgorithm, capacity: u64, now: i64) -> Self {
+        match algorithm {
+            RateLimitAlgorithm::SlidingLog => State::Log(VecDeque::new()),
+            RateLimitAlgorithm::SlidingWindow | RateLimitAlgorithm::FixedWindow => State::Window {
+                start: now,
+                count: 0,
+                previous: 0,
+            },
+            RateLimitAlgorithm::TokenBucket => State::Bucket {
+                tokens: capacity as f64,
+                updated_at: now,
+            },
+            RateLimitAlgorithm::Gcra => State::Gcra { tat: now as f64 },
+        }
+    }
+
+    fn is(&self, algorithm: RateLimitAlgorithm) -> bool {
+        matches!(
+            (self, algorithm),
+            (State::Log(_), RateLimitAlgorithm::SlidingLog)
+                | (State::Window { .. }, RateLimitAlgorithm::SlidingWindow)
+                | (State::Window { .. }, RateLimitAlgorithm::FixedWindow)
+                | (State::Bucket { .. }, RateLimitAlgorithm::TokenBucket)
+                | (State::Gcra { .. }, RateLimitAlgorithm::Gcra)
+        )
+    }
+
+    /// same as the redis scripts, also returns until when the state matters
+    fn acquire(&mut self, limiter: &RateLimiter, now: i64) -> (RateLimitDecision, i64) {
+        let window = limiter.interval_sec as i64 * 1000;
+        let permits = limiter.permits;
+        let capacity = limiter.capacity() as f64;
+        let decision = |permitted, limit, remaining: f64, reset_after: f64, retry_after: f64| {
+            RateLimitDecision {
+                permitted,
+                limit,
+                remaining: remaining.max(0.0).floor() as u64,
+                reset_after_ms: reset_after.max(0.0).ceil() as u64,
+                retry_after_ms: if permitted {
+                    0
+                } else {
+                    retry_after.max(1.0).ceil() as u64
+                },
+            }
+        };
+        match self {
+            State::Log(hits) => {
+                while hits.front().is_some_and(|hit| *hit <= now - window) {
+                    hits.pop_front();
+                }
+                let permitted = (hits.len() as u64) < permits;
+                if permitted {
+                    hits.push_back(now);
+                }
+                let oldest = hits.front().map_or(now, |hit| hit + window);
+                let newest = hits.back().map_or(now, |hit| hit + window);
+                (
+                    decision(
+                        permitted,
+                        permits,
+                        (permits - hits.len() as u64) as f64,
+                        (newest - now) as f64,
+                        (oldest - now) as f64,
+                    ),
+                    newest,
+                )
+            }
+            State::Window {
+                start,
+                count,
+                previous,
+            } => {
+                let elapsed = (now - *start) / window.max(1);
+                if elapsed >= 1 {
+                    *previous = if elapsed == 1 { *count } else { 0 };
+                    *start += elapsed * window;
+                    *count = 0;
+                }
+                let sliding = limiter.algorithm == RateLimitAlgorithm::SlidingWindow;
+                let weight = (window - (now - *start)) as f64 / window as f64;
+                let estimate = |count: u64| {
+                    if sliding {
+                        *previous as f64 * weight + count as f64
+                    } else {
+                        count as f64
+                    }
+                };
+                let permitted = estimate(*count) < permits as f64;
+                if permitted {
+                    *count += 1;
+                }
+                let window_end = (*start + window - now) as f64;
+                // when the weighted previous count has decayed enough
+                let retry_after = if !sliding {
+                    window_end
+                } else if *count < permits {
+                    *start as f64
+                        + window as f64 * (1.0 - (permits - *count) as f64 / *previous as f64)
+                        - now as f64
+                } else {
+                    window_end + window as f64 * (1.0 - permits as f64 / *count as f64)
+                };
+                let reset_after = if sliding {
+                    window_end + window as f64
+                } else {
+                    window_end
+                };
+                (
+                    decision(
+                        permitted,
+                        permits,
+                        permits as f64 - estimate(*count),
+                        reset_after,
+                        retry_after,
+                    ),
+                    *start + 2 * window,
+                )
+            }
+            State::Bucket { tokens, updated_at } => {
+                let rate = permits as f64 / window as f64;
+                *tokens = capacity.min(*tokens + (now - *updated_at) as f64 * rate);
+                *updated_at = now;
+                let permitted = *tokens >= 1.0;
+                if permitted {
+                    *tokens -= 1.0;
+                }
+                let reset_after = (capacity - *tokens) / rate;
+                (
+                    decision(
+                        permitted,
+                        capacity as u64,
+                        *tokens,
+                        reset_after,
+                        (1.0 - *tokens) / rate,
+                    ),
+                    now + reset_after.ceil() as i64,
+                )
+            }
+            State::Gcra { tat } => {
+                let emission = window as f64 / permits as f64;
+                let tolerance = emission * capacity;
+                let new_tat = tat.max(now as f64) + emission;
+                let permitted = new_tat - now as f64 <= tolerance;
+                if permitted {
+                    *tat = new_tat;
+                }
+                let reset_after = (*tat - now as f64).max(0.0);
+                (
+                    decision(
+                        permitted,
+                        capacity as u64,
+                        (tolerance - reset_after) / emission,
+                        reset_after,
+                        new_tat - now as f64 - tolerance,
+                    ),
+                    tat.ceil() as i64,
+                )
+            }
+        }
+    }
+}
+
+/// each shard drops its idle keys when touched, at most once per `cleanup_interval_sec`.
+pub struct InMemoryRateLimitStore {
+    shards: Vec<Mutex<Shard>>,
+    cleanup_interval_ms: i64,
+}
+
+impl InMemoryRateLimitStore {
+    pub fn new(config: &MemoryStoreConfig) -> Self {
+        Self {
+            shards: (0..config.shards.max(1))
+                .map(|_| Mutex::new(Shard::default()))
+                .collect(),
+            cleanup_interval_ms: config.cleanup_interval_sec * 1000,
+        }
+    }
+
+    fn shard_index(&self, key: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish() as usize % self.shards.len()
+    }
+}
+
+impl RateLimitStore for InMemoryRateLimitStore {
+    fn acquire<'a>(
+        &'a self,
+        limits: &'a [RateLimit<'a>],
+    ) -> BoxFuture<'a, Result<Vec<RateLimitDecision>, anyhow::Error>> {
+        Box::pin(async move {
+            let now = chrono::Utc::now().timestamp_millis();
+            let indexes = limits
+                .iter()
+                .map(|limit| self.shard_index(limit.key))
+                .collect::<Vec<_>>();
+            // shards are locked in order, concurrent batches never deadlock
+            let mut locked = indexes.clone();
+            locked.sort_unstable();
+            locked.dedup();
+            let mut shards = HashMap::with_capacity(locked.len());
+            for i in locked {
+                let mut shard = self.shards[i]
+                    .lock()
+                    .map_err(|_| anyhow!("rate limit store poisoned"))?;
+                if now - shard.cleaned_at >= self.cleanup_interval_ms {
+                    shard.entries.retain(|_, entry| entry.expire_at > now);
+                    shard.cleaned_at = now;
+                }
+                shards.insert(i, shard);
+            }
+            // evaluated on copies, which are kept only if every limit permits
+            let mut decisions = Vec::with_capacity(limits.len());
+            let mut entries = Vec::with_capacity(limits.len());
+            for (limit, i) in limits.iter().zip(indexes.iter()) {
+                let algorithm = limit.limiter.algorithm;
+                let mut state = shards[i]
+                    .entries
+                    .get(limit.key)
+                    // the rule may have changed its algorithm
+                    .filter(|entry| entry.state.is(algorithm))
+                    .map(|entry| entry.state.clone())
+                    .unwrap_or_else(|| State::new(algorithm, limit.limiter.capacity(), now));
+                let (decision, expire_at) = state.acquire(limit.limiter, now);
+                decisions.push(decision);
+                entries.push(Entry { state, expire_at });
+            }
+            if decisions.iter().all(|decision| decision.permitted) {
+                for ((limit, i), entry) in limits.iter().zip(indexes.iter()).zip(entries) {
+                    if let Some(shard) = shards.get_mut(i) {
+                        shard.entries.insert(limit.key.to_owned(), entry);
+                    }
+                }
+            }
+            Ok(decisions)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_algorithms() {
+        let store = InMemoryRateLimitStore::new(&MemoryStoreConfig::default());
+        super::super::tests::assert_algorithms(&store).await;
+        super::super::tests::assert_atomic(&store).await;
+    }
+}
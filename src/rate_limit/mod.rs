@@ -0,0 +1,240 @@
+use crate::config::{MemoryStoreConfig, RateLimitConfig, RateLimitStoreConfig, RateLimiter};
+use crate::utils::nonce::{InMemoryNonceStore, NonceStore};
+use futures_util::future::BoxFuture;
+use std::sync::Arc;
+
+mod circuit_breaker;
+mod concurrency;
+mod memory;
+#[cfg(feature = "redis")]
+mod redis_store;
+
+pub use circuit_breaker::CircuitBreaker;
+pub use concurrency::{ConcurrencyGate, ConcurrencyPermit};
+pub use memory::InMemoryRateLimitStore;
+#[cfg(feature = "redis")]
+pub use redis_store::RedisRateLimitStore;
+
+/// counts requests of rate limiters
+pub trait RateLimitStore: Send + Sync {
+    /// permits are taken from every limit, or from none if any of them has none left.
+    /// decisions are in the order of `limits`.
+    fn acquire<'a>(
+        &'a self,
+        limits: &'a [RateLimit<'a>],
+    ) -> BoxFuture<'a, Result<Vec<RateLimitDecision>, anyhow::Error>>;
+}
+
+/// the counter named `key` of `limiter`
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit<'a> {
+    pub key: &'a str,
+    pub limiter: &'a RateLimiter,
+}
+
+/// outcome of a permit acquisition, also inserted as a request extension for handlers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitDecision {
+    pub permitted: bool,
+    pub limit: u64,
+    pub remaining: u64,
+    /// until the budget is fully restored
+    pub reset_after_ms: u64,
+    /// until a rejected request may succeed, 0 if permitted
+    pub retry_after_ms: u64,
+}
+
+/// inserted in responses rejected by a rule, e.g. for metrics layers
+#[derive(Debug, Clone)]
+pub struct RateLimitRejection {
+    /// `RateLimiter::id` of the rule
+    pub rule: String,
+    pub decision: RateLimitDecision,
+}
+
+impl RateLimitDecision {
+    pub fn reset_after_sec(&self) -> u64 {
+        self.reset_after_ms.div_ceil(1000)
+    }
+
+    pub fn retry_after_sec(&self) -> u64 {
+        self.retry_after_ms.div_ceil(1000)
+    }
+}
+
+/// stores used by the rate limiter middleware
+#[derive(Clone)]
+pub struct RateLimitBackend {
+    pub limits: Arc<dyn RateLimitStore>,
+    /// remembers consumed forward keys
+    pub forward_keys: Arc<dyn NonceStore>,
+}
+
+impl RateLimitBackend {
+    pub fn new_in_memory(config: &MemoryStoreConfig) -> Self {
+        Self {
+            limits: Arc::new(InMemoryRateLimitStore::new(config)),
+            forward_keys: Arc::new(InMemoryNonceStore::new()),
+        }
+    }
+
+    /// shares the pool, e.g. with sessions
+    #[cfg(feature = "redis")]
+    pub fn new_redis(redis: fred::clients::RedisPool) -> Self {
+        Self {
+            limits: Arc::new(RedisRateLimitStore::new(redis.clone())),
+            forward_keys: Arc::new(crate::utils::nonce::RedisNonceStore::new(redis)),
+        }
+    }
+
+    /// the backend selected by `config.store`
+    pub async fn from_config(config: &RateLimitConfig) -> Result<Self, anyhow::Error> {
+        match &config.store {
+            RateLimitStoreConfig::Memory(memory) => Ok(Self::new_in_memory(memory)),
+            #[cfg(feature = "redis")]
+            RateLimitStoreConfig::Redis(redis) => {
+                Ok(Self::new_redis(crate::redis::new_pool(redis).await?))
+            }
+            #[cfg(not(feature = "redis"))]
+            RateLimitStoreConfig::Redis(_) => Err(anyhow::anyhow!(
+                "redis rate limit store requires the redis feature"
+            )),
+        }
+    }
+}
+
+/// behaviours every store must have, whatever the algorithm
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::{RateLimitAlgorithm, RateLimiterBuilder};
+    use std::time::Duration;
+
+    const ALGORITHMS: [RateLimitAlgorithm; 5] = [
+        RateLimitAlgorithm::SlidingLog,
+        RateLimitAlgorithm::SlidingWindow,
+        RateLimitAlgorithm::FixedWindow,
+        RateLimitAlgorithm::TokenBucket,
+        RateLimitAlgorithm::Gcra,
+    ];
+
+    async fn acquire(
+        store: &dyn RateLimitStore,
+        key: &str,
+        limiter: &RateLimiter,
+    ) -> RateLimitDecision {
+        store.acquire(&[RateLimit { key, limiter }]).await.unwrap()[0]
+    }
+
+    pub(crate) async fn assert_algorithms(store: &dyn RateLimitStore) {
+        let prefix = xid::new().to_string();
+        let limiter = |algorithm| {
+            RateLimiterBuilder::default()
+                .path("/".to_owned())
+                .interval_sec(1)
+                .permits(2)
+                .algorithm(algorithm)
+                .build()
+                .unwrap()
+        };
+        for algorithm in ALGORITHMS {
+            let limiter = limiter(algorithm);
+            let key = format!("{}:{:?}", prefix, algorithm);
+            let first = acquire(store, &key, &limiter).await;
+            assert!(first.permitted, "{:?}", algorithm);
+            assert_eq!((first.limit, first.remaining), (2, 1), "{:?}", algorithm);
+            // sliding windows are restored once the previous window stops weighing
+            assert!(first.reset_after_ms <= 2000, "{:?}", algorithm);
+            let second = acquire(store, &key, &limiter).await;
+            assert!(second.permitted, "{:?}", algorithm);
+            assert_eq!(second.remaining, 0, "{:?}", algorithm);
+            let rejected = acquire(store, &key, &limiter).await;
+            assert!(!rejected.permitted, "{:?}", algorithm);
+            assert!(
+                rejected.retry_after_ms > 0 && rejected.retry_after_ms <= 1000,
+                "{:?} retry after {}",
+                algorithm,
+                rejected.retry_after_ms
+            );
+            let other = format!("{}:other", key);
+            assert!(
+                acquire(store, &other, &limiter).await.permitted,
+                "{:?}",
+                algorithm
+            );
+        }
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        for algorithm in ALGORITHMS {
+            let key = format!("{}:{:?}", prefix, algorithm);
+            assert!(
+                acquire(store, &key, &limiter(algorithm)).await.permitted,
+                "{:?} not refilled",
+                algorithm
+            );
+        }
+        // bursts above the rate are only allowed by buckets
+        for algorithm in [RateLimitAlgorithm::TokenBucket, RateLimitAlgorithm::Gcra] {
+            let limiter = RateLimiter {
+                burst: Some(4),
+                ..limiter(algorithm)
+            };
+            let key = format!("{}:burst:{:?}", prefix, algorithm);
+            for _ in 0..4 {
+                assert!(
+                    acquire(store, &key, &limiter).await.permitted,
+                    "{:?}",
+                    algorithm
+                );
+            }
+            assert!(
+                !acquire(store, &key, &limiter).await.permitted,
+                "{:?}",
+                algorithm
+            );
+        }
+    }
+
+    /// a rule rejecting a request leaves the budgets of other rules untouched
+    pub(crate) async fn assert_atomic(store: &dyn RateLimitStore) {
+        let prefix = xid::new().to_string();
+        for algorithm in ALGORITHMS {
+            let limiter = |permits| {
+                RateLimiterBuilder::default()
+                    .interval_sec(60)
+                    .permits(permits)
+                    .algorithm(algorithm)
+                    .build()
+                    .unwrap()
+            };
+            let (loose, strict) = (limiter(2), limiter(1));
+            let loose_key = format!("{}:loose:{:?}", prefix, algorithm);
+            let strict_key = format!("{}:strict:{:?}", prefix, algorithm);
+            let limits = [
+                RateLimit {
+                    key: &loose_key,
+                    limiter: &loose,
+                },
+                RateLimit {
+                    key: &strict_key,
+                    limiter: &strict,
+                },
+            ];
+            let decisions = store.acquire(&limits).await.unwrap();
+            assert!(decisions.iter().all(|d| d.permitted), "{:?}", algorithm);
+            let decisions = store.acquire(&limits).await.unwrap();
+            assert!(decisions[0].permitted, "{:?}", algorithm);
+            assert!(!decisions[1].permitted, "{:?}", algorithm);
+            // the second permit of the loose rule was not taken
+            assert!(
+                acquire(store, &loose_key, &loose).await.permitted,
+                "{:?}",
+                algorithm
+            );
+            assert!(
+                !acquire(store, &loose_key, &loose).await.permitted,
+                "{:?}",
+                algorithm
+            );
+        }
+    }
+}
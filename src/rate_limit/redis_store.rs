@@ -0,0 +1,217 @@
+use super::{RateLimit, RateLimitDecision, RateLimitStore};
+use crate::config::RateLimitAlgorithm;
+use crate::redis::MustLoadScript;
+use anyhow::anyhow;
+use fred::clients::RedisPool;
+use fred::types::RedisValue;
+use futures_util::future::BoxFuture;
+
+const ACQUIRE_PERMITTED: i64 = 1;
+const REPLY_LEN: usize = 4;
+
+// each key of KEYS takes 4 ARGV: algorithm index, `interval_sec`, `permits` and `burst`.
+// every key is checked before any permit is taken, permits are taken only if all keys allow.
+// the reply holds `permitted, remaining, reset_after_ms, retry_after_ms` of each key.
+// times are taken from the server, the one clock of all instances.
+const RATE_LIMITER_SCRIPT: &str = r"
+            local current_time = redis.call('TIME')
+            local now = tonumber(current_time[1]) * 1000 + math.floor(tonumber(current_time[2]) / 1000)
+
+            local function sliding_log(key, window, permits)
+                redis.call('ZREMRANGEBYSCORE', key, 0, now - window)
+                local request_count = redis.call('ZCARD', key)
+                local oldest = redis.call('ZRANGE', key, 0, 0, 'WITHSCORES')
+                local newest = redis.call('ZRANGE', key, -1, -1, 'WITHSCORES')
+                if request_count >= permits then
+                    return {0, 0, tonumber(newest[2]) + window - now, tonumber(oldest[2]) + window - now}
+                end
+                return {1, permits - request_count - 1, window, 0}, function()
+                    redis.call('ZADD', key, now, current_time[1] .. current_time[2])
+                    redis.call('PEXPIRE', key, window)
+                end
+            end
+
+            local function window_counter(key, window, permits, sliding)
+                local state = redis.call('HMGET', key, 'start', 'count', 'previous')
+                local start = tonumber(state[1]) or now
+                local count = tonumber(state[2]) or 0
+                local previous = tonumber(state[3]) or 0
+                local elapsed = math.floor((now - start) / window)
+                if elapsed >= 1 then
+                    if elapsed == 1 then previous = count else previous = 0 end
+                    start = start + elapsed * window
+                    count = 0
+                end
+                if not sliding then previous = 0 end
+                local weight = (window - (now - start)) / window
+                local window_end = start + window - now
+                local reset = window_end
+                if sliding then reset = window_end + window end
+                if previous * weight + count >= permits then
+                    local retry = window_end
+                    if sliding and count < permits then
+                        retry = start + window * (1 - (permits - count) / previous) - now
+                    elseif sliding then
+                        retry = window_end + window * (1 - permits / count)
+                    end
+                    return {0, 0, reset, math.ceil(retry)}
+                end
+                return {1, math.floor(permits - previous * weight - count - 1), reset, 0}, function()
+                    redis.call('HSET', key, 'start', start, 'count', count + 1, 'previous', previous)
+                    redis.call('PEXPIRE', key, start + 2 * window - now)
+                end
+            end
+
+            local function sliding_window(key, window, permits)
+                return window_counter(key, window, permits, true)
+            end
+
+            local function fixed_window(key, window, permits)
+                return window_counter(key, window, permits, false)
+            end
+
+            local function token_bucket(key, window, permits, capacity)
+                local rate = permits / window
+                local state = redis.call('HMGET', key, 'tokens', 'updated_at')
+                local tokens = tonumber(state[1]) or capacity
+                local updated_at = tonumber(state[2]) or now
+                tokens = math.min(capacity, tokens + (now - updated_at) * rate)
+                if tokens < 1 then
+                    return {0, 0, math.ceil((capacity - tokens) / rate), math.ceil((1 - tokens) / rate)}
+                end
+                tokens = tokens - 1
+                local reset = math.ceil((capacity - tokens) / rate)
+                return {1, math.floor(tokens), reset, 0}, function()
+                    redis.call('HSET', key, 'tokens', tostring(tokens), 'updated_at', now)
+                    redis.call('PEXPIRE', key, reset + 1)
+                end
+            end
+
+            local function gcra(key, window, permits, capacity)
+                local emission = window / permits
+                local tolerance = emission * capacity
+                local tat = math.max(tonumber(redis.call('GET', key)) or now, now)
+                local new_tat = tat + emission
+                if new_tat - now > tolerance then
+                    return {0, 0, math.ceil(tat - now), math.ceil(new_tat - now - tolerance)}
+                end
+                local remaining = math.floor((tolerance - (new_tat - now)) / emission)
+                return {1, remaining, math.ceil(new_tat - now), 0}, function()
+                    redis.call('SET', key, tostring(new_tat), 'PX', math.ceil(new_tat - now))
+                end
+            end
+
+            local algorithms = {sliding_log, sliding_window, fixed_window, token_bucket, gcra}
+            local replies, commits, permitted = {}, {}, true
+            for i, key in ipairs(KEYS) do
+                local offset = (i - 1) * 4
+                local algorithm = algorithms[tonumber(ARGV[offset + 1])]
+                local reply, commit = algorithm(key, tonumber(ARGV[offset + 2]) * 1000,
+                    tonumber(ARGV[offset + 3]), tonumber(ARGV[offset + 4]))
+                for _, value in ipairs(reply) do
+                    table.insert(replies, value)
+                end
+                if commit then
+                    table.insert(commits, commit)
+                else
+                    permitted = false
+                end
+            end
+            if permitted then
+                for _, commit in ipairs(commits) do
+                    commit()
+                end
+            end
+            return replies
+        ";
+
+/// shared by instances, the limits of a request are evaluated by one lua script.
+/// with redis cluster, keys of a request must hash to the same slot.
+pub struct RedisRateLimitStore {
+    redis: RedisPool,
+    script: MustLoadScript,
+}
+
+impl RedisRateLimitStore {
+    pub fn new(redis: RedisPool) -> Self {
+        Self {
+            redis,
+            script: MustLoadScript::new(RATE_LIMITER_SCRIPT),
+        }
+    }
+}
+
+/// index of the algorithm in the script
+fn algorithm_index(algorithm: RateLimitAlgorithm) -> i64 {
+    match algorithm {
+        RateLimitAlgorithm::SlidingLog => 1,
+        RateLimitAlgorithm::SlidingWindow => 2,
+        RateLimitAlgorithm::FixedWindow => 3,
+        RateLimitAlgorithm::TokenBucket => 4,
+        RateLimitAlgorithm::Gcra => 5,
+    }
+}
+
+impl RateLimitStore for RedisRateLimitStore {
+    fn acquire<'a>(
+        &'a self,
+        limits: &'a [RateLimit<'a>],
+    ) -> BoxFuture<'a, Result<Vec<RateLimitDecision>, anyhow::Error>> {
+        Box::pin(async move {
+            // keys of other algorithms hold other types
+            let keys = limits
+                .iter()
+                .map(|limit| format!("{}:{:?}", limit.key, limit.limiter.algorithm))
+                .collect::<Vec<_>>();
+            let args = limits
+                .iter()
+                .flat_map(|limit| {
+                    [
+                        algorithm_index(limit.limiter.algorithm),
+                        limit.limiter.interval_sec as i64,
+                        limit.limiter.permits as i64,
+                        limit.limiter.capacity() as i64,
+                    ]
+                })
+                .map(RedisValue::from)
+                .collect::<Vec<_>>();
+            let reply: Vec<i64> = self.script.evalsha(&self.redis, keys, args).await?;
+            if reply.len() != limits.len() * REPLY_LEN {
+                return Err(anyhow!("unexpected rate limit script reply {:?}", reply));
+            }
+            Ok(limits
+                .iter()
+                .zip(reply.chunks(REPLY_LEN))
+                .map(|(limit, reply)| {
+                    let permitted = reply[0] == ACQUIRE_PERMITTED;
+                    let limit = match limit.limiter.algorithm {
+                        RateLimitAlgorithm::TokenBucket | RateLimitAlgorithm::Gcra => {
+                            limit.limiter.capacity()
+                        }
+                        _ => limit.limiter.permits,
+                    };
+                    RateLimitDecision {
+                        permitted,
+                        limit,
+                        remaining: reply[1].max(0) as u64,
+                        reset_after_ms: reply[2].max(0) as u64,
+                        retry_after_ms: if permitted { 0 } else { reply[3].max(1) as u64 },
+                    }
+                })
+                .collect())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_algorithms() {
+        let store = RedisRateLimitStore::new(crate::redis::tests::test_pool().await);
+        super::super::tests::assert_algorithms(&store).await;
+        super::super::tests::assert_atomic(&store).await;
+    }
+}
@@ -0,0 +1,84 @@
+use crate::config::RedisConfig;
+use anyhow::anyhow;
+use fred::clients::RedisPool;
+use fred::interfaces::ClientLike;
+use fred::types::{Builder as PoolBuilder, ReconnectPolicy};
+use std::time::Duration;
+use tracing::info;
+
+mod script;
+
+pub use script::MustLoadScript;
+
+const RECONNECT_MIN_DELAY_MS: u32 = 100;
+const RECONNECT_MAX_DELAY_MS: u32 = 30_000;
+
+/// connected pool, fails if the server can not be reached at start up.
+/// lost connections are re-established with exponential backoff.
+pub async fn new_pool(config: &RedisConfig) -> Result<RedisPool, anyhow::Error> {
+    let pool = PoolBuilder::from_config(fred::types::RedisConfig::from_url(&config.url)?)
+        .with_connection_config(|c| {
+            c.connection_timeout = Duration::from_secs(config.connect_timeout_sec);
+        })
+        .set_policy(ReconnectPolicy::new_exponential(
+            config.max_reconnect_attempts,
+            RECONNECT_MIN_DELAY_MS,
+            RECONNECT_MAX_DELAY_MS,
+            2,
+        ))
+        .build_pool(config.pool_size)?;
+    pool.init().await?;
+    info!(pool_size = config.pool_size, "redis connected");
+    Ok(pool)
+}
+
+/// `PING` through every connection of the pool
+pub async fn health_check(redis: &RedisPool) -> Result<(), anyhow::Error> {
+    for client in redis.clients() {
+        let pong: String = client.ping().await?;
+        if pong != "PONG" {
+            return Err(anyhow!("unexpected ping reply {}", pong));
+        }
+    }
+    Ok(())
+}
+
+/// run with `cargo test --features redis -- --ignored` against a local `redis-server`,
+/// `REDIS_URL` overrides the default address
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::RedisConfigBuilder;
+    use fred::interfaces::LuaInterface;
+
+    pub(crate) async fn test_pool() -> RedisPool {
+        let url = std::env::var("REDIS_URL").unwrap_or("redis://127.0.0.1:6379".to_owned());
+        let config = RedisConfigBuilder::default()
+            .url(url)
+            .pool_size(2)
+            .build()
+            .unwrap();
+        new_pool(&config).await.unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_pool_and_script_reload() {
+        let redis = test_pool().await;
+        health_check(&redis).await.unwrap();
+
+        let script = MustLoadScript::new("return tonumber(ARGV[1]) + 1");
+        let two: i64 = script
+            .evalsha(&redis, Vec::<String>::new(), 1)
+            .await
+            .unwrap();
+        assert_eq!(two, 2);
+        // e.g. a restarted server
+        redis.script_flush(false).await.unwrap();
+        let three: i64 = script
+            .evalsha(&redis, Vec::<String>::new(), 2)
+            .await
+            .unwrap();
+        assert_eq!(three, 3);
+    }
+}
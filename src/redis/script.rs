@@ -0,0 +1,38 @@
+use fred::clients::RedisPool;
+use fred::error::RedisError;
+use fred::types::{FromRedis, MultipleKeys, MultipleValues, Script};
+
+/// lua script invoked by `EVALSHA`.
+/// it is loaded on `NOSCRIPT`, i.e. on first use and after the server lost its script cache.
+pub struct MustLoadScript {
+    script: Script,
+}
+
+impl MustLoadScript {
+    pub fn new(lua: &'static str) -> Self {
+        Self {
+            script: Script::from_lua(lua),
+        }
+    }
+
+    pub fn sha1(&self) -> &str {
+        self.script.sha1()
+    }
+
+    pub async fn evalsha<R, K, V>(
+        &self,
+        redis: &RedisPool,
+        keys: K,
+        args: V,
+    ) -> Result<R, RedisError>
+    where
+        R: FromRedis,
+        K: Into<MultipleKeys> + Send,
+        V: TryInto<MultipleValues> + Send,
+        V::Error: Into<RedisError> + Send,
+    {
+        self.script
+            .evalsha_with_reload(redis.next(), keys, args)
+            .await
+    }
+}
@@ -9,8 +9,8 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 const SIGNED_CONTENT_SEPARATOR: &str = "@";
 
-pub fn signing_none_secret(raw: &str) -> String {
-    let digest = digest(&SHA256, raw.as_bytes());
+pub fn signing_none_secret<T: AsRef<[u8]>>(raw: T) -> String {
+    let digest = digest(&SHA256, raw.as_ref());
     hex::encode(digest)
 }
 
@@ -28,7 +28,7 @@ pub fn signed_content<T: Serialize + ?Sized>(
     Ok(base64::encode(signed_content))
 }
 
-pub fn parse_signed_content<T: DeserializeOwned + ?Sized>(
+pub fn parse_signed_content<T: DeserializeOwned>(
     signed: &str,
     secret: &str,
 ) -> Result<T, anyhow::Error> {
@@ -36,8 +36,7 @@ pub fn parse_signed_content<T: DeserializeOwned + ?Sized>(
     let mut parts = decode.split(SIGNED_CONTENT_SEPARATOR);
     let sign = parts.next().ok_or(anyhow!("no signature decoded"))?;
     let content = parts.next().ok_or(anyhow!("no raw content decoded"))?;
-    let verify = signing(content, secret);
-    if verify.eq(sign) {
+    if verify_signing(content, secret, sign) {
         let decode = base64::decode(content)?;
         return Ok(serde_json::from_slice(&decode)?);
     }
@@ -49,8 +48,21 @@ pub fn signing(raw: &str, secret: &str) -> String {
         &hmac::Key::new(HMAC_SHA256, secret.as_bytes()),
         raw.as_bytes(),
     );
-    let signature = hex::encode(signature);
-    signature
+    hex::encode(signature)
+}
+
+/// constant time comparing against a hex signature made by `signing`
+pub fn verify_signing(raw: &str, secret: &str, signature: &str) -> bool {
+    hex::decode(signature)
+        .map(|signature| {
+            hmac::verify(
+                &hmac::Key::new(HMAC_SHA256, secret.as_bytes()),
+                raw.as_bytes(),
+                &signature,
+            )
+            .is_ok()
+        })
+        .unwrap_or(false)
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -107,6 +119,9 @@ mod tests {
         let signed = signed_content(raw, secret).unwrap();
         let parsed: String = parse_signed_content(&signed, secret).unwrap();
         assert_eq!(parsed, raw);
+        assert!(parse_signed_content::<String>(&signed, "other").is_err());
+        assert!(verify_signing(raw, secret, &signing(raw, secret)));
+        assert!(!verify_signing(raw, "other", &signing(raw, secret)));
         let signed_content = SignedContent::new(raw.to_owned());
         let signed = signed_content.to_signed_string(secret).unwrap();
         let parse: SignedContent<String> = SignedContent::parse(&signed, secret).unwrap();
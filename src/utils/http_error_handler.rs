@@ -18,6 +18,8 @@ pub struct ErrorCode {
     status_code: StatusCode,
     // may use for meaningful status
     code: Option<u16>,
+    // machine readable error, e.g. `invalid_token`
+    error: Option<String>,
 }
 
 impl Serialize for ErrorCode {
@@ -38,6 +40,7 @@ impl ErrorCode {
         Self {
             status_code,
             code: None,
+            error: None,
         }
     }
 
@@ -45,6 +48,7 @@ impl ErrorCode {
         Self {
             status_code: StatusCode::BAD_REQUEST,
             code: None,
+            error: None,
         }
     }
 
@@ -52,6 +56,7 @@ impl ErrorCode {
         Self {
             status_code: StatusCode::INTERNAL_SERVER_ERROR,
             code: None,
+            error: None,
         }
     }
 
@@ -59,6 +64,11 @@ impl ErrorCode {
         self.code = Some(code);
         self
     }
+
+    pub fn error(&mut self, error: &str) -> &Self {
+        self.error = Some(error.to_owned());
+        self
+    }
 }
 
 #[derive(Debug)]
@@ -105,6 +115,12 @@ impl ErrorResponse {
     pub fn new_no_auth() -> Self {
         Self::new_with_status_code(StatusCode::UNAUTHORIZED)
     }
+
+    pub fn new_with_error(status_code: StatusCode, error: &str, message: Option<String>) -> Self {
+        let mut code = ErrorCode::from_status_code(status_code);
+        code.error(error);
+        Self::AppError { code, message }
+    }
 }
 
 macro_rules! match_rejection {
@@ -113,7 +129,7 @@ macro_rules! match_rejection {
         match $e.downcast_ref::<$rej>() {
             Some(e) => {
                 info!(e = ?e, "general bad params rejection incur");
-                return into_json_response(StatusCode::BAD_REQUEST, &e.body_text());
+                return into_json_response(StatusCode::BAD_REQUEST, &e.body_text(), None);
             },
             _ => {}
         }
@@ -121,13 +137,17 @@ macro_rules! match_rejection {
     };
 }
 
-fn into_json_response(status_code: StatusCode, message: &str) -> Response {
+fn into_json_response(status_code: StatusCode, message: &str, error: Option<&str>) -> Response {
+    let mut body = json!({
+        "code": status_code.as_u16(),
+        "message": message
+    });
+    if let Some(error) = error {
+        body["error"] = json!(error);
+    }
     (
         status_code,
-        serde_json::to_string(&json!({
-            "code": status_code.as_u16(),
-            "message": message
-        }))
+        serde_json::to_string(&body)
         // must not be failed
         .unwrap_or("{}".to_owned()),
     )
@@ -157,7 +177,7 @@ impl IntoResponse for ErrorResponse {
                 let message = message
                     .as_deref()
                     .unwrap_or(code.status_code.canonical_reason().unwrap_or(ERROR_UNKNOWN));
-                return into_json_response(code.status_code, message);
+                return into_json_response(code.status_code, message, code.error.as_deref());
             }
         }
         into_json_response(
@@ -165,6 +185,7 @@ impl IntoResponse for ErrorResponse {
             StatusCode::INTERNAL_SERVER_ERROR
                 .canonical_reason()
                 .unwrap_or(ERROR_UNKNOWN),
+            None,
         )
     }
 }
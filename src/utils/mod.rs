@@ -5,3 +5,4 @@ pub mod http_error_handler;
 pub mod hash;
 pub mod random;
 pub mod pager;
+pub mod nonce;
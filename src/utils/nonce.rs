@@ -0,0 +1,137 @@
+use anyhow::anyhow;
+use futures_util::future::BoxFuture;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// remembers nonces for a while so that they can be used only once
+pub trait NonceStore: Send + Sync {
+    /// `Ok(true)` if the nonce was never seen within its ttl, it is recorded then.
+    fn check_and_set<'a>(
+        &'a self,
+        nonce: &'a str,
+        ttl_sec: i64,
+    ) -> BoxFuture<'a, Result<bool, anyhow::Error>>;
+}
+
+#[derive(Default)]
+pub struct InMemoryNonceStore {
+    // nonce -> expire at
+    nonces: Mutex<HashMap<String, i64>>,
+    last_purged_at: Mutex<i64>,
+}
+
+impl InMemoryNonceStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn purge_expired(&self, nonces: &mut HashMap<String, i64>, now: i64) {
+        if let Ok(mut last_purged_at) = self.last_purged_at.lock() {
+            if *last_purged_at < now {
+                nonces.retain(|_, expire_at| *expire_at > now);
+                *last_purged_at = now;
+            }
+        }
+    }
+}
+
+impl NonceStore for InMemoryNonceStore {
+    fn check_and_set<'a>(
+        &'a self,
+        nonce: &'a str,
+        ttl_sec: i64,
+    ) -> BoxFuture<'a, Result<bool, anyhow::Error>> {
+        Box::pin(async move {
+            let now = chrono::Utc::now().timestamp();
+            let mut nonces = self
+                .nonces
+                .lock()
+                .map_err(|_| anyhow!("nonce store poisoned"))?;
+            self.purge_expired(&mut nonces, now);
+            if nonces.get(nonce).is_some_and(|expire_at| *expire_at > now) {
+                return Ok(false);
+            }
+            nonces.insert(nonce.to_owned(), now + ttl_sec);
+            Ok(true)
+        })
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use redis_store::RedisNonceStore;
+
+#[cfg(feature = "redis")]
+mod redis_store {
+    use super::*;
+    use fred::clients::RedisPool;
+    use fred::interfaces::KeysInterface;
+    use fred::types::{Expiration, SetOptions};
+
+    const NONCE_KEY_PREFIX: &str = "nonce:";
+
+    /// shared by instances, nonces expire with their keys
+    pub struct RedisNonceStore {
+        redis: RedisPool,
+    }
+
+    impl RedisNonceStore {
+        pub fn new(redis: RedisPool) -> Self {
+            Self { redis }
+        }
+    }
+
+    impl NonceStore for RedisNonceStore {
+        fn check_and_set<'a>(
+            &'a self,
+            nonce: &'a str,
+            ttl_sec: i64,
+        ) -> BoxFuture<'a, Result<bool, anyhow::Error>> {
+            Box::pin(async move {
+                // `SET NX` replies nil if the key exists
+                let reply: Option<String> = self
+                    .redis
+                    .set(
+                        format!("{}{}", NONCE_KEY_PREFIX, nonce),
+                        1,
+                        Some(Expiration::EX(ttl_sec.max(1))),
+                        Some(SetOptions::NX),
+                        false,
+                    )
+                    .await?;
+                Ok(reply.is_some())
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn assert_once(store: &dyn NonceStore) {
+        let nonce = xid::new().to_string();
+        assert!(store.check_and_set(&nonce, 60).await.unwrap());
+        assert!(!store.check_and_set(&nonce, 60).await.unwrap());
+        assert!(store
+            .check_and_set(&xid::new().to_string(), 60)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_nonce_store() {
+        let store = InMemoryNonceStore::new();
+        assert_once(&store).await;
+        // expired nonces can be used again
+        assert!(store.check_and_set("expired", 0).await.unwrap());
+        assert!(store.check_and_set("expired", 60).await.unwrap());
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore = "requires a local redis-server"]
+    async fn test_redis_nonce_store() {
+        let store = RedisNonceStore::new(crate::redis::tests::test_pool().await);
+        assert_once(&store).await;
+    }
+}
@@ -26,6 +26,6 @@ impl Default for Pager {
 
 impl Pager {
     pub fn offset(&self) -> u64 {
-        return (self.page_num - 1) * self.page_size;
+        (self.page_num - 1) * self.page_size
     }
 }